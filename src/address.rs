@@ -0,0 +1,167 @@
+use bech32::{FromBase32, Variant};
+
+pub const MAINNET_HRP: &str = "sh";
+
+// A decoded segwit address: witness version and program, as in BIP 173
+// (version 0, bech32) and BIP 350 (version 1 and up, bech32m).
+pub struct WitnessProgram {
+    pub version: u8,
+    pub program: Vec<u8>,
+}
+
+impl WitnessProgram {
+    pub fn script_pubkey(&self) -> Vec<u8> {
+        let mut script = Vec::with_capacity(self.program.len() + 2);
+        // OP_0 for version 0, OP_1..OP_16 after that
+        script.push(if self.version == 0 { 0x00 } else { 0x50 + self.version });
+        script.push(self.program.len() as u8);
+        script.extend_from_slice(&self.program);
+        script
+    }
+}
+
+pub fn decode_address(address: &str, expected_hrp: &str) -> Result<WitnessProgram, String> {
+    let (hrp, data, variant) =
+        bech32::decode(address).map_err(|e| format!("{} is not a valid bech32 address: {}", address, e))?;
+
+    if hrp != expected_hrp {
+        return Err(format!(
+            "{} is for network prefix '{}' but '{}' was expected",
+            address, hrp, expected_hrp
+        ));
+    }
+
+    let (version, program) = data
+        .split_first()
+        .ok_or_else(|| format!("{} has no witness version", address))?;
+    let version = version.to_u8();
+    if version > 16 {
+        return Err(format!("{} has invalid witness version {}", address, version));
+    }
+
+    let expected_variant = if version == 0 { Variant::Bech32 } else { Variant::Bech32m };
+    if variant != expected_variant {
+        return Err(format!(
+            "{} uses the wrong checksum for witness version {} (expected {:?})",
+            address, version, expected_variant
+        ));
+    }
+
+    let program = Vec::<u8>::from_base32(program)
+        .map_err(|e| format!("{} has an invalid witness program: {}", address, e))?;
+    if !(2..=40).contains(&program.len()) {
+        return Err(format!("{} has a witness program of invalid length {}", address, program.len()));
+    }
+    if version == 0 && program.len() != 20 && program.len() != 32 {
+        return Err(format!(
+            "{} is a version 0 address but its program is {} bytes instead of 20 or 32",
+            address,
+            program.len()
+        ));
+    }
+
+    Ok(WitnessProgram { version, program })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script_hex(address: &str, hrp: &str) -> Result<String, String> {
+        decode_address(address, hrp).map(|program| hex::encode(program.script_pubkey()))
+    }
+
+    // Valid segwit addresses from BIP 173 and BIP 350 with their scriptPubKeys.
+    #[test]
+    fn valid_addresses() {
+        for (address, hrp, script) in [
+            ("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", "bc", "0014751e76e8199196d454941c45d1b3a323f1433bd6"),
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                "tb",
+                "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            ),
+            (
+                "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y",
+                "bc",
+                "5128751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6",
+            ),
+            ("BC1SW50QGDZ25J", "bc", "6002751e"),
+            ("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs", "bc", "5210751e76e8199196d454941c45d1b3a323"),
+            (
+                "tb1qqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesrxh6hy",
+                "tb",
+                "0020000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+            ),
+            (
+                "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+                "tb",
+                "5120000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433",
+            ),
+            (
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                "bc",
+                "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            ),
+            ("sh1qeexkz69dz6j4q0zt0pkn36650yevwc8eksqeuu", MAINNET_HRP, "0014ce4d6168ad16a5503c4b786d38eb547932c760f9"),
+        ] {
+            assert_eq!(script_hex(address, hrp).as_deref(), Ok(script), "{}", address);
+        }
+    }
+
+    // Invalid segwit addresses from BIP 173 and BIP 350.
+    #[test]
+    fn invalid_addresses() {
+        for (address, hrp) in [
+            // BIP 173
+            ("tc1qw508d6qejxtdg4y5r3zarvary0c5xw7kg3g4ty", "bc"),
+            ("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5", "bc"),
+            ("BC13W508D6QEJXTDG4Y5R3ZARVARY0C5XW7KN40WF2", "bc"),
+            ("bc1rw5uspcuh", "bc"),
+            ("bc10w508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kw5rljs90", "bc"),
+            ("BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P", "bc"),
+            ("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sL5k7", "tb"),
+            ("bc1zw508d6qejxtdg4y5r3zarvaryvqyzf3du", "bc"),
+            ("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3pjxtptv", "tb"),
+            ("bc1gmk9yu", "bc"),
+            // BIP 350
+            ("tc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq5zuyut", "bc"),
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd", "bc"),
+            ("tb1z0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqglt7rf", "tb"),
+            ("BC1S0XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ54WELL", "bc"),
+            ("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh", "bc"),
+            ("tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47", "tb"),
+            ("bc1p38j9r5y49hruaue7wxjce0updqjuyyx0kh56v8s25huc6995vvpql3jow4", "bc"),
+            ("BC130XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ7ZWS8R", "bc"),
+            ("bc1pw5dgrnzv", "bc"),
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v8n0nx0muaewav253zgeav", "bc"),
+            ("BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P", "bc"),
+            ("tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq47Zagq", "tb"),
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7v07qwwzcrf", "bc"),
+            ("tb1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vpggkg4j", "tb"),
+            ("bc1gmk9yu", "bc"),
+        ] {
+            assert!(decode_address(address, hrp).is_err(), "{} should be rejected", address);
+        }
+    }
+
+    // Version 0 programs must be 20 or 32 bytes even though 2 to 40 is
+    // allowed for later versions.
+    #[test]
+    fn v0_program_length() {
+        let error = decode_address("BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P", "bc").err().unwrap();
+        assert!(error.contains("version 0"), "{}", error);
+    }
+
+    #[test]
+    fn mixed_case_rejected() {
+        let error = decode_address("sh1qeexkz69dz6j4q0zt0pkn36650yevwc8eksqeuU", MAINNET_HRP).err().unwrap();
+        assert!(error.contains("mixed"), "{}", error);
+    }
+
+    #[test]
+    fn wrong_network() {
+        let error = decode_address("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", MAINNET_HRP).err().unwrap();
+        assert!(error.contains("network prefix"), "{}", error);
+    }
+}
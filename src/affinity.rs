@@ -0,0 +1,127 @@
+use std::fmt;
+use std::str::FromStr;
+
+// A set of CPU ids as written for taskset and cgroups: "0-15,32-47".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CpuList(pub Vec<usize>);
+
+impl FromStr for CpuList {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut cpus = Vec::new();
+        for part in value.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let parse = |id: &str| id.trim().parse::<usize>().map_err(|_| format!("invalid CPU id '{}' in '{}'", id, value));
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (parse(first)?, parse(last)?);
+                    if first > last {
+                        return Err(format!("invalid CPU range '{}'", part));
+                    }
+                    cpus.extend(first..=last);
+                }
+                None => cpus.push(parse(part)?),
+            }
+        }
+        if cpus.is_empty() {
+            return Err(String::from("the CPU list is empty"));
+        }
+        let mut seen = std::collections::HashSet::new();
+        cpus.retain(|cpu| seen.insert(*cpu));
+        Ok(CpuList(cpus))
+    }
+}
+
+impl fmt::Display for CpuList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for &cpu in &self.0 {
+            match ranges.last_mut() {
+                Some((_, last)) if *last + 1 == cpu => *last = cpu,
+                _ => ranges.push((cpu, cpu)),
+            }
+        }
+        let parts: Vec<String> = ranges
+            .iter()
+            .map(|&(first, last)| if first == last { first.to_string() } else { format!("{}-{}", first, last) })
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+impl CpuList {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // The CPU for the worker at `index`, wrapping when there are more
+    // workers than CPUs.
+    pub fn for_worker(&self, index: usize) -> usize {
+        self.0[index % self.0.len()]
+    }
+}
+
+// Restricts the calling thread to the given CPUs.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpus: &[usize]) -> Result<(), String> {
+    if let Some(cpu) = cpus.iter().find(|&&cpu| cpu >= libc::CPU_SETSIZE as usize) {
+        return Err(format!("CPU {} is out of range", cpu));
+    }
+    // SAFETY: cpu_set_t is a plain bitmask, valid when zeroed, and every
+    // CPU was checked against its size above.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpus: &[usize]) -> Result<(), String> {
+    Err(String::from("CPU pinning is only supported on Linux"))
+}
+
+// The CPU the calling thread is running on right now.
+#[cfg(target_os = "linux")]
+pub fn current_cpu() -> Option<usize> {
+    // SAFETY: sched_getcpu takes no arguments and only reports.
+    let cpu = unsafe { libc::sched_getcpu() };
+    usize::try_from(cpu).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn current_cpu() -> Option<usize> {
+    None
+}
+
+// Drops the calling thread to the lowest priority: nice 19 and SCHED_IDLE,
+// so it only runs when nothing else wants the CPU.
+#[cfg(target_os = "linux")]
+pub fn lower_current_thread_priority() -> Result<(), String> {
+    // SAFETY: plain syscalls on the calling thread with valid arguments.
+    unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        if libc::setpriority(libc::PRIO_PROCESS, tid, 19) != 0 {
+            return Err(format!("setpriority: {}", std::io::Error::last_os_error()));
+        }
+        let param = libc::sched_param { sched_priority: 0 };
+        if libc::sched_setscheduler(0, libc::SCHED_IDLE, &param) != 0 {
+            return Err(format!("sched_setscheduler: {}", std::io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn lower_current_thread_priority() -> Result<(), String> {
+    Err(String::from("background mode is only supported on Linux"))
+}
@@ -1,9 +1,17 @@
 use warp::Filter;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use tokio::sync::Mutex;
 use serde::Serialize;
 use std::time::{Instant, Duration};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use crate::connection::{ConnectionState, ConnectionStatus};
+use crate::load::LoadEvent;
+use crate::config::DEFAULT_VDFTIME_MS;
+use crate::metrics::{self, Exposition, Histogram};
+use crate::models::MINER_VERSION;
+use crate::vdftune::{VdfTuneStatus, VdfTuner};
 
 #[derive(Serialize)]
 pub struct Stats {
@@ -12,6 +20,92 @@ pub struct Stats {
     pub rejected: usize,
     pub version: String,
     pub uptime: u64,
+    pub connection: ConnectionStatus,
+    pub keepalive: KeepaliveStats,
+    pub pool: PoolStatus,
+    pub difficulty: f64,
+    pub accepted_work: f64,
+    pub stale_target_shares: usize,
+    pub workers: usize,
+    pub configured_workers: usize,
+    pub numa_nodes: Vec<NodeStats>,
+    pub load: LoadStats,
+    pub schedule: Option<ScheduleStatus>,
+    pub search: SearchStats,
+}
+
+// Cycle search throughput, kept apart from the hash rate: every attempt
+// either solves a cycle (and yields a hash) or bails out at the vdftime.
+#[derive(Serialize)]
+pub struct SearchStats {
+    pub attempts: usize,
+    pub solved: usize,
+    pub bailouts: usize,
+    pub attempts_per_second: f64,
+    pub solved_per_second: f64,
+    pub bailout_ratio: f64,
+    pub vdftime_ms: u64,
+    pub solve_times: Vec<SolveTimeBucket>,
+    // The last --vdftime auto evaluation and why it chose what it did.
+    pub vdftime_auto: Option<VdfTuneStatus>,
+}
+
+// Solved searches that finished within `budget_fraction` of the vdftime,
+// and above the previous bucket's bound. The last bucket, without a
+// bound, holds searches that overran the budget slightly before bailing.
+#[derive(Serialize)]
+pub struct SolveTimeBucket {
+    pub budget_fraction: Option<f64>,
+    pub count: u64,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct SearchRates {
+    pub attempts_per_second: f64,
+    pub solved_per_second: f64,
+    pub bailout_ratio: f64,
+}
+
+// Whether the mining schedule currently allows work and when that changes.
+#[derive(Serialize, Clone)]
+pub struct ScheduleStatus {
+    pub schedule: String,
+    pub mining: bool,
+    pub next_transition: Option<String>,
+}
+
+// What --busy-threshold saw of other processes and what it did about it.
+#[derive(Serialize)]
+pub struct LoadStats {
+    pub other_cpus: f64,
+    pub events: Vec<LoadEvent>,
+}
+
+// Latest figures reported by the pool itself, so they can be compared
+// against what the miner measures locally.
+#[derive(Serialize, Clone, Default)]
+pub struct PoolStatus {
+    pub pplns_score: Option<f64>,
+    pub hashrate: Option<f64>,
+    pub balance: Option<f64>,
+    pub last_payout: Option<Payout>,
+    pub last_notice: Option<String>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Payout {
+    pub amount: f64,
+    pub txid: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct KeepaliveStats {
+    pub pings_sent: usize,
+    pub pongs_received: usize,
+    pub ping_rtt_ms: usize,
+    pub idle_timeouts: usize,
+    pub stale_job_timeouts: usize,
 }
 
 pub struct MinerState {
@@ -20,6 +114,193 @@ pub struct MinerState {
     pub rejected_shares: Arc<AtomicUsize>,
     pub hashrate_samples: Arc<Mutex<Vec<u64>>>,
     pub version: String,
+    // When the miner started; uptime is measured from here so it survives
+    // the API being rebound on a reload.
+    pub start_time: Instant,
+    pub connection: Arc<Mutex<ConnectionStatus>>,
+    pub pings_sent: Arc<AtomicUsize>,
+    pub pongs_received: Arc<AtomicUsize>,
+    pub ping_rtt_ms: Arc<AtomicUsize>,
+    pub idle_timeouts: Arc<AtomicUsize>,
+    pub stale_job_timeouts: Arc<AtomicUsize>,
+    pub pool_status: Arc<Mutex<PoolStatus>>,
+    pub difficulty: Arc<Mutex<f64>>,
+    pub accepted_work: Arc<Mutex<f64>>,
+    pub pending_shares: Arc<Mutex<VecDeque<PendingShare>>>,
+    pub stale_target_shares: Arc<AtomicUsize>,
+    pub workers: Arc<AtomicUsize>,
+    pub configured_workers: Arc<AtomicUsize>,
+    pub numa_nodes: Arc<Vec<NodeCounters>>,
+    pub other_cpus: Arc<Mutex<f64>>,
+    pub load_events: Arc<Mutex<VecDeque<LoadEvent>>>,
+    pub schedule: Arc<Mutex<Option<ScheduleStatus>>>,
+    pub attempts: Arc<AtomicUsize>,
+    pub bailouts: Arc<AtomicUsize>,
+    pub solve_times: Arc<Histogram>,
+    pub solve_budget: Arc<Histogram>,
+    pub vdftime_ms: Arc<AtomicU64>,
+    // Set with --vdftime auto; workers then take each search's bailout from it.
+    pub vdf_tuner: Option<Arc<VdfTuner>>,
+    pub search_rates: Arc<Mutex<SearchRates>>,
+    pub submit_latency: Arc<Histogram>,
+    // Indexed by worker slot; slots outlive retired workers so their
+    // totals are kept.
+    pub worker_counters: Arc<std::sync::Mutex<Vec<Arc<WorkerCounters>>>>,
+}
+
+// Everything zeroed, no NUMA nodes and the default vdftime; the miner
+// overrides what its configuration decides.
+impl Default for MinerState {
+    fn default() -> MinerState {
+        MinerState {
+            hash_count: Arc::new(AtomicUsize::new(0)),
+            accepted_shares: Arc::new(AtomicUsize::new(0)),
+            rejected_shares: Arc::new(AtomicUsize::new(0)),
+            hashrate_samples: Arc::new(Mutex::new(Vec::new())),
+            version: String::from(MINER_VERSION),
+            start_time: Instant::now(),
+            connection: Arc::new(Mutex::new(ConnectionStatus::new())),
+            pings_sent: Arc::new(AtomicUsize::new(0)),
+            pongs_received: Arc::new(AtomicUsize::new(0)),
+            ping_rtt_ms: Arc::new(AtomicUsize::new(0)),
+            idle_timeouts: Arc::new(AtomicUsize::new(0)),
+            stale_job_timeouts: Arc::new(AtomicUsize::new(0)),
+            pool_status: Arc::new(Mutex::new(PoolStatus::default())),
+            difficulty: Arc::new(Mutex::new(0.0)),
+            accepted_work: Arc::new(Mutex::new(0.0)),
+            pending_shares: Arc::new(Mutex::new(VecDeque::new())),
+            stale_target_shares: Arc::new(AtomicUsize::new(0)),
+            workers: Arc::new(AtomicUsize::new(0)),
+            configured_workers: Arc::new(AtomicUsize::new(0)),
+            numa_nodes: Arc::new(Vec::new()),
+            other_cpus: Arc::new(Mutex::new(0.0)),
+            load_events: Arc::new(Mutex::new(VecDeque::new())),
+            schedule: Arc::new(Mutex::new(None)),
+            attempts: Arc::new(AtomicUsize::new(0)),
+            bailouts: Arc::new(AtomicUsize::new(0)),
+            solve_times: Arc::new(Histogram::new(metrics::SOLVE_TIME_BUCKETS)),
+            solve_budget: Arc::new(Histogram::new(metrics::SOLVE_BUDGET_BUCKETS)),
+            vdftime_ms: Arc::new(AtomicU64::new(DEFAULT_VDFTIME_MS)),
+            vdf_tuner: None,
+            search_rates: Arc::new(Mutex::new(SearchRates::default())),
+            submit_latency: Arc::new(Histogram::new(metrics::SUBMIT_LATENCY_BUCKETS)),
+            worker_counters: Arc::new(std::sync::Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl MinerState {
+    // The counters for worker slot `index`, created on first use.
+    pub fn worker_counters(&self, index: usize) -> Arc<WorkerCounters> {
+        let mut counters = self.worker_counters.lock().unwrap_or_else(|e| e.into_inner());
+        while counters.len() <= index {
+            let worker = counters.len();
+            counters.push(Arc::new(WorkerCounters::new(worker)));
+        }
+        counters[index].clone()
+    }
+
+    pub fn worker_stats(&self) -> Vec<WorkerStats> {
+        let counters = self.worker_counters.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        counters.iter().map(|worker| worker.stats()).collect()
+    }
+}
+
+// A share sent to the pool or node and not answered yet.
+pub struct PendingShare {
+    pub difficulty: f64,
+    pub submitted: Instant,
+}
+
+pub struct WorkerCounters {
+    pub worker: usize,
+    pub running: AtomicBool,
+    pub cpus: std::sync::Mutex<Option<String>>,
+    pub last_cpu: AtomicUsize,
+    pub attempts: AtomicUsize,
+    pub hash_count: AtomicUsize,
+    pub bailouts: AtomicUsize,
+    pub solve_micros: AtomicU64,
+    pub shares: AtomicUsize,
+    pub last_activity: AtomicU64,
+    pub hashrate: AtomicUsize,
+}
+
+impl WorkerCounters {
+    fn new(worker: usize) -> WorkerCounters {
+        WorkerCounters {
+            worker,
+            running: AtomicBool::new(false),
+            cpus: std::sync::Mutex::new(None),
+            last_cpu: AtomicUsize::new(usize::MAX),
+            attempts: AtomicUsize::new(0),
+            hash_count: AtomicUsize::new(0),
+            bailouts: AtomicUsize::new(0),
+            solve_micros: AtomicU64::new(0),
+            shares: AtomicUsize::new(0),
+            last_activity: AtomicU64::new(0),
+            hashrate: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> WorkerStats {
+        let attempts = self.attempts.load(Ordering::Relaxed);
+        let cycles_found = self.hash_count.load(Ordering::Relaxed);
+        let bailouts = self.bailouts.load(Ordering::Relaxed);
+        let last_cpu = self.last_cpu.load(Ordering::Relaxed);
+        let last_activity = self.last_activity.load(Ordering::Relaxed);
+        WorkerStats {
+            worker: self.worker,
+            running: self.running.load(Ordering::Relaxed),
+            cpus: self.cpus.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+            last_cpu: (last_cpu != usize::MAX).then_some(last_cpu),
+            attempts,
+            cycles_found,
+            bailouts,
+            avg_solve_ms: if cycles_found == 0 {
+                0.0
+            } else {
+                self.solve_micros.load(Ordering::Relaxed) as f64 / cycles_found as f64 / 1000.0
+            },
+            shares: self.shares.load(Ordering::Relaxed),
+            hashrate: self.hashrate.load(Ordering::Relaxed),
+            last_activity: (last_activity != 0).then_some(last_activity),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct WorkerStats {
+    pub worker: usize,
+    pub running: bool,
+    // CPUs the worker is pinned to, and the one it last ran a search on.
+    pub cpus: Option<String>,
+    pub last_cpu: Option<usize>,
+    pub attempts: usize,
+    pub cycles_found: usize,
+    pub bailouts: usize,
+    pub avg_solve_ms: f64,
+    pub shares: usize,
+    pub hashrate: usize,
+    // Unix time of the last finished cycle search.
+    pub last_activity: Option<u64>,
+}
+
+// Per NUMA node counters, only filled in when workers are placed by node.
+pub struct NodeCounters {
+    pub node: usize,
+    pub cpus: String,
+    pub workers: AtomicUsize,
+    pub hash_count: AtomicUsize,
+    pub hashrate: AtomicUsize,
+}
+
+#[derive(Serialize)]
+pub struct NodeStats {
+    pub node: usize,
+    pub cpus: String,
+    pub workers: usize,
+    pub hashrate: usize,
 }
 
 async fn calculate_avg_hashrate(samples: Arc<Mutex<Vec<u64>>>) -> u64 {
@@ -36,12 +317,59 @@ fn calculate_uptime(start_time: Instant) -> u64 {
     elapsed.as_secs()
 }
 
-async fn stats_handler(state: Arc<MinerState>, start_time: Instant) -> Result<impl warp::Reply, warp::Rejection> {
+async fn stats_handler(state: Arc<MinerState>) -> Result<impl warp::Reply, warp::Rejection> {
     let avg_hashrate = calculate_avg_hashrate(state.hashrate_samples.clone()).await;
     let accepted = state.accepted_shares.load(Ordering::Relaxed);
     let rejected = state.rejected_shares.load(Ordering::Relaxed);
     let version = state.version.clone();
-    let uptime = calculate_uptime(start_time);
+    let uptime = calculate_uptime(state.start_time);
+    let connection = state.connection.lock().await.clone();
+    let pool = state.pool_status.lock().await.clone();
+    let difficulty = *state.difficulty.lock().await;
+    let accepted_work = *state.accepted_work.lock().await;
+    let stale_target_shares = state.stale_target_shares.load(Ordering::Relaxed);
+    let workers = state.workers.load(Ordering::Relaxed);
+    let configured_workers = state.configured_workers.load(Ordering::Relaxed);
+    let load = LoadStats {
+        other_cpus: *state.other_cpus.lock().await,
+        events: state.load_events.lock().await.iter().cloned().collect(),
+    };
+    let numa_nodes = state
+        .numa_nodes
+        .iter()
+        .map(|node| NodeStats {
+            node: node.node,
+            cpus: node.cpus.clone(),
+            workers: node.workers.load(Ordering::Relaxed),
+            hashrate: node.hashrate.load(Ordering::Relaxed),
+        })
+        .collect();
+    let schedule = state.schedule.lock().await.clone();
+    let rates = *state.search_rates.lock().await;
+    let solve_budget = state.solve_budget.snapshot();
+    let search = SearchStats {
+        attempts: state.attempts.load(Ordering::Relaxed),
+        solved: state.hash_count.load(Ordering::Relaxed),
+        bailouts: state.bailouts.load(Ordering::Relaxed),
+        attempts_per_second: rates.attempts_per_second,
+        solved_per_second: rates.solved_per_second,
+        bailout_ratio: rates.bailout_ratio,
+        vdftime_ms: state.vdftime_ms.load(Ordering::Relaxed),
+        solve_times: solve_budget
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(index, count)| SolveTimeBucket { budget_fraction: solve_budget.bounds.get(index).copied(), count: *count })
+            .collect(),
+        vdftime_auto: state.vdf_tuner.as_ref().and_then(|tuner| tuner.status()),
+    };
+    let keepalive = KeepaliveStats {
+        pings_sent: state.pings_sent.load(Ordering::Relaxed),
+        pongs_received: state.pongs_received.load(Ordering::Relaxed),
+        ping_rtt_ms: state.ping_rtt_ms.load(Ordering::Relaxed),
+        idle_timeouts: state.idle_timeouts.load(Ordering::Relaxed),
+        stale_job_timeouts: state.stale_job_timeouts.load(Ordering::Relaxed),
+    };
 
     let stats = Stats {
         hashrate: avg_hashrate,
@@ -49,20 +377,148 @@ async fn stats_handler(state: Arc<MinerState>, start_time: Instant) -> Result<im
         rejected,
         version,
         uptime,
+        connection,
+        keepalive,
+        pool,
+        difficulty,
+        accepted_work,
+        stale_target_shares,
+        workers,
+        configured_workers,
+        numa_nodes,
+        load,
+        schedule,
+        search,
     };
 
     Ok(warp::reply::json(&stats))
 }
 
-pub async fn start_http_server(state: Arc<MinerState>) {
-    let start_time = Instant::now();
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+// Passes requests carrying `Authorization: Bearer <token>`, or every
+// request when no token is configured.
+fn authorized(token: Option<Arc<String>>) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let token = token.clone();
+            async move {
+                let Some(token) = token else {
+                    return Ok(());
+                };
+                let presented = header.as_deref().and_then(|header| header.strip_prefix("Bearer ")).unwrap_or("");
+                if constant_time_eq(presented.as_bytes(), token.as_bytes()) {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn handle_rejection(rejection: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        let reply = warp::reply::with_status("unauthorized\n", warp::http::StatusCode::UNAUTHORIZED);
+        return Ok(warp::reply::with_header(reply, "www-authenticate", "Bearer"));
+    }
+    Err(rejection)
+}
+
+async fn workers_handler(state: Arc<MinerState>) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&state.worker_stats()))
+}
+
+async fn metrics_handler(state: Arc<MinerState>) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut metrics = Exposition::default();
+    metrics.family("shaipot_build_info", "gauge", "Always 1, labelled with the miner version.");
+    metrics.sample("shaipot_build_info", &[("version", &state.version)], 1.0);
+    metrics.gauge("shaipot_uptime_seconds", "Seconds since the miner started.", state.start_time.elapsed().as_secs_f64());
+
+    let status = state.connection.lock().await.clone();
+    metrics.family("shaipot_connection_state", "gauge", "1 for the current pool or node connection state, 0 for the others.");
+    for connection_state in ConnectionState::ALL {
+        let current = if connection_state == status.state { 1.0 } else { 0.0 };
+        metrics.sample("shaipot_connection_state", &[("state", &connection_state.to_string())], current);
+    }
+    metrics.counter("shaipot_reconnects_total", "Connection attempts after the first one.", status.reconnects as f64);
+
+    let solved = state.hash_count.load(Ordering::Relaxed);
+    metrics.counter("shaipot_hashes_total", "Hashes computed, one per solved cycle.", solved as f64);
+    metrics.family("shaipot_cycle_searches_total", "counter", "Hamiltonian cycle searches by outcome.");
+    metrics.sample("shaipot_cycle_searches_total", &[("result", "solved")], solved as f64);
+    metrics.sample("shaipot_cycle_searches_total", &[("result", "bailout")], state.bailouts.load(Ordering::Relaxed) as f64);
+    metrics.histogram("shaipot_cycle_solve_seconds", "Time taken by successful cycle searches.", &state.solve_times.snapshot());
+    metrics.histogram(
+        "shaipot_cycle_solve_budget_ratio",
+        "Time taken by successful cycle searches as a share of the vdftime budget.",
+        &state.solve_budget.snapshot(),
+    );
+    metrics.gauge("shaipot_vdftime_seconds", "Current vdftime budget for a cycle search.", state.vdftime_ms.load(Ordering::Relaxed) as f64 / 1000.0);
+    metrics.gauge("shaipot_hashrate", "Hashes per second averaged over the last 10 seconds.", calculate_avg_hashrate(state.hashrate_samples.clone()).await as f64);
+
+    metrics.family("shaipot_shares_total", "counter", "Shares by outcome; stale shares were dropped before submitting because the target rose.");
+    metrics.sample("shaipot_shares_total", &[("result", "accepted")], state.accepted_shares.load(Ordering::Relaxed) as f64);
+    metrics.sample("shaipot_shares_total", &[("result", "rejected")], state.rejected_shares.load(Ordering::Relaxed) as f64);
+    metrics.sample("shaipot_shares_total", &[("result", "stale")], state.stale_target_shares.load(Ordering::Relaxed) as f64);
+    metrics.histogram("shaipot_share_submit_latency_seconds", "Time from submitting a share to the answer.", &state.submit_latency.snapshot());
+    metrics.gauge("shaipot_difficulty", "Current share difficulty.", *state.difficulty.lock().await);
+
+    metrics.gauge("shaipot_workers", "Worker threads currently running.", state.workers.load(Ordering::Relaxed) as f64);
+    let counters: Vec<Arc<WorkerCounters>> = state.worker_counters.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    metrics.family("shaipot_worker_hashrate", "gauge", "Hashes per second of each worker over the last log interval.");
+    for worker in &counters {
+        metrics.sample("shaipot_worker_hashrate", &[("worker", &worker.worker.to_string())], worker.hashrate.load(Ordering::Relaxed) as f64);
+    }
+
+    let reply = warp::reply::with_header(metrics.finish(), "content-type", "text/plain; version=0.0.4");
+    Ok(reply)
+}
+
+// Binds the stats API and returns the server to be spawned, so a port that
+// is already taken is reported instead of leaving a dead task behind.
+pub fn bind_http_server(
+    state: Arc<MinerState>,
+    bind: SocketAddr,
+    token: Option<String>,
+) -> Result<impl std::future::Future<Output = ()>, String> {
+    let auth = authorized(token.map(Arc::new));
 
     let stats_route = warp::path("stats")
         .and(warp::get())
+        .and(auth.clone())
         .and_then({
             let state = state.clone();
-            move || stats_handler(state.clone(), start_time)
+            move || stats_handler(state.clone())
         });
 
-    warp::serve(stats_route).run(([127, 0, 0, 1], 8844)).await;
+    let workers_route = warp::path("workers")
+        .and(warp::get())
+        .and(auth.clone())
+        .and_then({
+            let state = state.clone();
+            move || workers_handler(state.clone())
+        });
+
+    let metrics_route = warp::path("metrics")
+        .and(warp::get())
+        .and(auth)
+        .and_then({
+            let state = state.clone();
+            move || metrics_handler(state.clone())
+        });
+
+    let routes = stats_route.or(workers_route).or(metrics_route).recover(handle_rejection);
+
+    warp::serve(routes)
+        .try_bind_ephemeral(bind)
+        .map(|(_, server)| server)
+        .map_err(|e| format!("Failed to start the stats API on {}: {}", bind, e))
 }
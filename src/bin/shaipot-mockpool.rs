@@ -0,0 +1,345 @@
+// A stand-in pool speaking the same WebSocket JSON protocol as a real one,
+// so the miner can be exercised end to end without network access.
+//
+//   shaipot-mockpool --bind 127.0.0.1:8855 --script "job,wait:10,close:1012:restart"
+//   shaipot --address <addr> --pool ws://127.0.0.1:8855
+//
+// Each connection runs the script once, then keeps answering submits (and
+// hands out a new job every --job-interval seconds if set).
+use clap::Parser;
+use colored::*;
+use rand::Rng;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::sync::Mutex;
+use tokio_tungstenite::accept_async;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use shaipot::hasher::verify_solution;
+use shaipot::models::Job;
+use shaipot::utils::meets_target;
+use shaipot::vdf_solution::HCGraphUtil;
+
+#[derive(Parser, Debug)]
+struct MockArgs {
+    #[clap(long, default_value = "127.0.0.1:8855")]
+    bind: String,
+    // Share target handed out with every job; the default accepts any
+    // valid cycle so shares arrive quickly.
+    #[clap(long, default_value = "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")]
+    target: String,
+    #[clap(long, default_value = "job")]
+    script: String,
+    #[clap(long)]
+    job_interval: Option<f64>,
+    // Answer every login with login_rejected and this message.
+    #[clap(long, value_name = "MESSAGE")]
+    reject_login: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    Job,
+    Wait(Duration),
+    Malformed,
+    Unknown,
+    Notice(String),
+    Target(String),
+    Difficulty(f64),
+    Close(u16, String),
+    Drop,
+    Repeat,
+}
+
+fn parse_script(script: &str) -> Result<Vec<Step>, String> {
+    script
+        .split(',')
+        .map(str::trim)
+        .filter(|step| !step.is_empty())
+        .map(|step| {
+            let (name, arg) = match step.split_once(':') {
+                Some((name, arg)) => (name, Some(arg)),
+                None => (step, None),
+            };
+            let require = |what: &str| arg.ok_or_else(|| format!("step '{}' needs {}", name, what));
+            match name {
+                "job" => Ok(Step::Job),
+                "wait" => require("seconds")?
+                    .parse::<f64>()
+                    .map(|secs| Step::Wait(Duration::from_secs_f64(secs)))
+                    .map_err(|e| format!("invalid wait '{}': {}", step, e)),
+                "malformed" => Ok(Step::Malformed),
+                "unknown" => Ok(Step::Unknown),
+                "notice" => Ok(Step::Notice(require("a message")?.to_string())),
+                "target" => Ok(Step::Target(require("a hex target")?.to_string())),
+                "difficulty" => require("a number")?
+                    .parse::<f64>()
+                    .map(Step::Difficulty)
+                    .map_err(|e| format!("invalid difficulty '{}': {}", step, e)),
+                "close" => {
+                    let arg = require("a close code")?;
+                    let (code, reason) = arg.split_once(':').unwrap_or((arg, ""));
+                    let code = code.parse::<u16>().map_err(|e| format!("invalid close code '{}': {}", step, e))?;
+                    Ok(Step::Close(code, reason.to_string()))
+                }
+                "drop" => Ok(Step::Drop),
+                "repeat" => Ok(Step::Repeat),
+                _ => Err(format!("unknown script step '{}'", step)),
+            }
+        })
+        .collect()
+}
+
+enum Outgoing {
+    Frame(Message),
+    Drop,
+}
+
+#[derive(Default)]
+struct PoolStats {
+    connections: AtomicUsize,
+    jobs: AtomicUsize,
+    accepted: AtomicUsize,
+    rejected: AtomicUsize,
+}
+
+struct Session {
+    peer: String,
+    // The address given at login; submits for any other are rejected.
+    miner_id: Mutex<Option<String>>,
+    target: Mutex<String>,
+    jobs: Mutex<HashMap<String, Job>>,
+    next_job_id: AtomicUsize,
+    outgoing: UnboundedSender<Outgoing>,
+    stats: Arc<PoolStats>,
+}
+
+impl Session {
+    fn send(&self, value: Value) {
+        self.send_raw(&value.to_string());
+    }
+
+    fn send_raw(&self, text: &str) {
+        let _ = self.outgoing.send(Outgoing::Frame(Message::Text(text.to_string())));
+    }
+
+    async fn send_job(&self) {
+        let job_id = format!("{:x}", self.next_job_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let header: [u8; 76] = std::array::from_fn(|_| rand::thread_rng().gen());
+        let job = Job {
+            job_id: job_id.clone(),
+            data: hex::encode(header),
+            target: self.target.lock().await.clone(),
+            nonce_range: None,
+        };
+        self.send(json!({ "type": "job", "job_id": job.job_id, "data": job.data, "target": job.target }));
+        self.jobs.lock().await.insert(job_id.clone(), job);
+        self.stats.jobs.fetch_add(1, Ordering::Relaxed);
+        println!("{} {}", self.peer.bright_black(), format!("job {}", job_id).blue());
+    }
+
+    async fn handle_submit(&self, submit: &Value, verifier: &HCGraphUtil) {
+        let field = |name: &str| submit.get(name).and_then(Value::as_str).unwrap_or_default().to_string();
+        let (job_id, nonce, path) = (field("job_id"), field("nonce"), field("path"));
+        let login = self.miner_id.lock().await.clone();
+
+        let job = self.jobs.lock().await.get(&job_id).cloned();
+        let verdict = match job {
+            _ if login.as_deref() != Some(field("miner_id").as_str()) => Err(String::from("miner_id does not match login")),
+            None => Err(String::from("unknown job")),
+            Some(job) => match verify_solution(&format!("{}{}", job.data, nonce), &path, verifier) {
+                None => Err(String::from("invalid path")),
+                Some(hash) if !meets_target(&hash, &self.target.lock().await) => Err(String::from("above target")),
+                Some(hash) => Ok(hash),
+            },
+        };
+
+        match verdict {
+            Ok(hash) => {
+                self.stats.accepted.fetch_add(1, Ordering::Relaxed);
+                println!("{} {}", self.peer.bright_black(), format!("accepted {} for job {}", hash, job_id).green());
+                self.send(json!({ "type": "accepted" }));
+                // The miner drops its job after a share, so hand out the next one.
+                self.send_job().await;
+            }
+            Err(reason) => {
+                self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+                println!("{} {}", self.peer.bright_black(), format!("rejected share for job {}: {}", job_id, reason).red());
+                self.send(json!({ "type": "rejected", "message": reason }));
+            }
+        }
+    }
+}
+
+async fn run_script(session: Arc<Session>, steps: Vec<Step>, job_interval: Option<Duration>) {
+    let mut index = 0;
+    while index < steps.len() {
+        match &steps[index] {
+            Step::Job => session.send_job().await,
+            Step::Wait(delay) => tokio::time::sleep(*delay).await,
+            Step::Malformed => session.send_raw("{\"type\": \"job\", \"job_id\":"),
+            Step::Unknown => session.send(json!({ "type": "mystery", "message": "not part of the protocol" })),
+            Step::Notice(message) => session.send(json!({ "type": "notice", "message": message })),
+            Step::Target(target) => {
+                *session.target.lock().await = target.clone();
+                session.send(json!({ "type": "set_target", "target": target }));
+            }
+            Step::Difficulty(difficulty) => {
+                if let Some(target) = shaipot::utils::difficulty_to_target(*difficulty) {
+                    *session.target.lock().await = target;
+                }
+                session.send(json!({ "type": "set_difficulty", "difficulty": difficulty }));
+            }
+            Step::Close(code, reason) => {
+                let frame = CloseFrame {
+                    code: CloseCode::from(*code),
+                    reason: Cow::Owned(reason.clone()),
+                };
+                let _ = session.outgoing.send(Outgoing::Frame(Message::Close(Some(frame))));
+                let _ = session.outgoing.send(Outgoing::Drop);
+                return;
+            }
+            Step::Drop => {
+                let _ = session.outgoing.send(Outgoing::Drop);
+                return;
+            }
+            Step::Repeat => {
+                index = 0;
+                continue;
+            }
+        }
+        index += 1;
+    }
+
+    if let Some(interval) = job_interval {
+        loop {
+            tokio::time::sleep(interval).await;
+            session.send_job().await;
+        }
+    }
+}
+
+async fn handle_connection(stream: TcpStream, args: Arc<MockArgs>, steps: Vec<Step>, stats: Arc<PoolStats>) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    let ws_stream = match accept_async(stream).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            println!("{}", format!("{} handshake failed: {}", peer, e).red());
+            return;
+        }
+    };
+    stats.connections.fetch_add(1, Ordering::Relaxed);
+    println!("{} {}", peer.bright_black(), "connected".green());
+
+    let (mut write, mut read) = ws_stream.split();
+    let (outgoing, mut outgoing_receiver) = unbounded_channel::<Outgoing>();
+    let session = Arc::new(Session {
+        peer: peer.clone(),
+        miner_id: Mutex::new(None),
+        target: Mutex::new(args.target.clone()),
+        jobs: Mutex::new(HashMap::new()),
+        next_job_id: AtomicUsize::new(0),
+        outgoing,
+        stats,
+    });
+
+    let job_interval = args.job_interval.map(Duration::from_secs_f64);
+    let script = tokio::spawn(run_script(session.clone(), steps, job_interval));
+    let verifier = HCGraphUtil::new(None);
+
+    loop {
+        tokio::select! {
+            outgoing = outgoing_receiver.recv() => match outgoing {
+                Some(Outgoing::Frame(msg)) => {
+                    if write.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Outgoing::Drop) | None => break,
+            },
+            incoming = read.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let Ok(msg) = serde_json::from_str::<Value>(&text) else {
+                        println!("{} {}", peer.bright_black(), "sent a malformed frame".red());
+                        continue;
+                    };
+                    match msg.get("type").and_then(Value::as_str) {
+                        Some("login") => {
+                            println!("{} {}", peer.bright_black(), format!("login {}", msg).cyan());
+                            *session.miner_id.lock().await = msg.get("miner_id").and_then(Value::as_str).map(String::from);
+                            match &args.reject_login {
+                                Some(message) => session.send(json!({ "type": "login_rejected", "message": message })),
+                                None => session.send(json!({ "type": "login_accepted" })),
+                            }
+                        }
+                        Some("submit") => session.handle_submit(&msg, &verifier).await,
+                        _ => println!("{} {}", peer.bright_black(), format!("ignoring {}", text).yellow()),
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    script.abort();
+    println!("{} {}", peer.bright_black(), "disconnected".yellow());
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Arc::new(MockArgs::parse());
+    let steps = match parse_script(&args.script) {
+        Ok(steps) => steps,
+        Err(e) => {
+            println!("{}", e.bold().red());
+            std::process::exit(2);
+        }
+    };
+
+    let listener = match TcpListener::bind(&args.bind).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("{}", format!("Failed to bind {}: {}", args.bind, e).bold().red());
+            std::process::exit(1);
+        }
+    };
+    // Report the bound address so `--bind 127.0.0.1:0` tells callers the port.
+    let local_addr = listener.local_addr().map(|addr| addr.to_string()).unwrap_or_else(|_| args.bind.clone());
+    println!("{} {}", "Mock pool listening on".bold().green(), format!("ws://{}", local_addr).bold().cyan());
+
+    let stats = Arc::new(PoolStats::default());
+    let summary_stats = stats.clone();
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        println!(
+            "{}",
+            format!(
+                "connections: {}, jobs: {}, accepted: {}, rejected: {}",
+                summary_stats.connections.load(Ordering::Relaxed),
+                summary_stats.jobs.load(Ordering::Relaxed),
+                summary_stats.accepted.load(Ordering::Relaxed),
+                summary_stats.rejected.load(Ordering::Relaxed),
+            )
+            .bold()
+        );
+        std::process::exit(0);
+    });
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(stream, args.clone(), steps.clone(), stats.clone()));
+            }
+            Err(e) => println!("{}", format!("accept failed: {}", e).red()),
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BlockTemplate {
+    pub version: u32,
+    pub previousblockhash: String,
+    pub transactions: Vec<TemplateTransaction>,
+    pub coinbasevalue: u64,
+    pub default_witness_commitment: Option<String>,
+    pub target: String,
+    pub curtime: u32,
+    pub bits: String,
+    pub height: u64,
+    pub longpollid: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TemplateTransaction {
+    pub data: String,
+    pub txid: String,
+}
+
+pub fn sha256d(data: &[u8]) -> [u8; 32] {
+    let first = Sha256::digest(data);
+    Sha256::digest(first).into()
+}
+
+// RPC hashes are displayed byte-reversed; this returns them in the
+// internal order used inside headers and merkle trees.
+pub fn hash_from_rpc_hex(hash: &str) -> Result<[u8; 32], String> {
+    let mut bytes: [u8; 32] = hex::decode(hash)
+        .map_err(|e| format!("invalid hash {}: {}", hash, e))?
+        .try_into()
+        .map_err(|_| format!("hash {} is not 32 bytes", hash))?;
+    bytes.reverse();
+    Ok(bytes)
+}
+
+pub fn merkle_root(mut level: Vec<[u8; 32]>) -> [u8; 32] {
+    if level.is_empty() {
+        return [0u8; 32];
+    }
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(*level.last().unwrap());
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let mut joined = [0u8; 64];
+                joined[..32].copy_from_slice(&pair[0]);
+                joined[32..].copy_from_slice(&pair[1]);
+                sha256d(&joined)
+            })
+            .collect();
+    }
+    level[0]
+}
+
+pub fn write_varint(out: &mut Vec<u8>, n: u64) {
+    match n {
+        0..=0xfc => out.push(n as u8),
+        0xfd..=0xffff => {
+            out.push(0xfd);
+            out.extend_from_slice(&(n as u16).to_le_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            out.push(0xfe);
+            out.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+        _ => {
+            out.push(0xff);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+    }
+}
+
+// Pushed into the coinbase scriptSig after the BIP 34 height.
+const COINBASE_TAG: &[u8] = b"/shaipot/";
+
+pub struct Coinbase {
+    pub data: String,
+    pub txid: [u8; 32],
+}
+
+// Serializes a height the way `CScript() << nHeight` does: OP_0 and
+// OP_1..OP_16 for small values, otherwise a minimal little-endian push.
+fn push_height(script: &mut Vec<u8>, height: u64) {
+    match height {
+        0 => script.push(0x00),
+        1..=16 => script.push(0x50 + height as u8),
+        _ => {
+            let mut bytes = Vec::new();
+            let mut n = height;
+            while n > 0 {
+                bytes.push((n & 0xff) as u8);
+                n >>= 8;
+            }
+            // Script numbers are signed, keep the top bit clear.
+            if bytes.last().is_some_and(|b| b & 0x80 != 0) {
+                bytes.push(0x00);
+            }
+            script.push(bytes.len() as u8);
+            script.extend_from_slice(&bytes);
+        }
+    }
+}
+
+// Builds a coinbase paying the whole template reward to `script_pubkey`.
+// When the template carries a witness commitment it is added as a second
+// output and the coinbase gets the all-zero witness reserved value.
+pub fn build_coinbase(template: &BlockTemplate, script_pubkey: &[u8]) -> Result<Coinbase, String> {
+    let mut script_sig = Vec::new();
+    push_height(&mut script_sig, template.height);
+    script_sig.push(COINBASE_TAG.len() as u8);
+    script_sig.extend_from_slice(COINBASE_TAG);
+
+    let mut input = Vec::new();
+    input.extend_from_slice(&[0u8; 32]);
+    input.extend_from_slice(&u32::MAX.to_le_bytes());
+    write_varint(&mut input, script_sig.len() as u64);
+    input.extend_from_slice(&script_sig);
+    input.extend_from_slice(&u32::MAX.to_le_bytes());
+
+    let commitment = template
+        .default_witness_commitment
+        .as_ref()
+        .map(|c| hex::decode(c).map_err(|e| format!("invalid witness commitment: {}", e)))
+        .transpose()?;
+
+    let mut outputs = Vec::new();
+    write_varint(&mut outputs, if commitment.is_some() { 2 } else { 1 });
+    outputs.extend_from_slice(&template.coinbasevalue.to_le_bytes());
+    write_varint(&mut outputs, script_pubkey.len() as u64);
+    outputs.extend_from_slice(script_pubkey);
+    if let Some(commitment) = &commitment {
+        outputs.extend_from_slice(&0u64.to_le_bytes());
+        write_varint(&mut outputs, commitment.len() as u64);
+        outputs.extend_from_slice(commitment);
+    }
+
+    let version = 2u32.to_le_bytes();
+    let lock_time = 0u32.to_le_bytes();
+
+    let mut stripped = Vec::new();
+    stripped.extend_from_slice(&version);
+    write_varint(&mut stripped, 1);
+    stripped.extend_from_slice(&input);
+    stripped.extend_from_slice(&outputs);
+    stripped.extend_from_slice(&lock_time);
+    let txid = sha256d(&stripped);
+
+    if commitment.is_none() {
+        return Ok(Coinbase { data: hex::encode(stripped), txid });
+    }
+
+    let mut full = Vec::new();
+    full.extend_from_slice(&version);
+    full.extend_from_slice(&[0x00, 0x01]);
+    write_varint(&mut full, 1);
+    full.extend_from_slice(&input);
+    full.extend_from_slice(&outputs);
+    full.push(0x01);
+    full.push(0x20);
+    full.extend_from_slice(&[0u8; 32]);
+    full.extend_from_slice(&lock_time);
+
+    Ok(Coinbase { data: hex::encode(full), txid })
+}
+
+// The merkle root over the coinbase and the template's transactions,
+// using txids (not wtxids) in internal byte order.
+pub fn template_merkle_root(template: &BlockTemplate, coinbase_txid: [u8; 32]) -> Result<[u8; 32], String> {
+    let mut txids = Vec::with_capacity(template.transactions.len() + 1);
+    txids.push(coinbase_txid);
+    for tx in &template.transactions {
+        txids.push(hash_from_rpc_hex(&tx.txid)?);
+    }
+    Ok(merkle_root(txids))
+}
+
+// Block header layout, all integers little-endian:
+//
+//   version (4) | previous block hash (32) | merkle root (32) | time (4) |
+//   bits (4) | nonce (4) | vdf solution (2008 x u16)
+//
+// The header fields that precede the nonce, hex encoded. This is exactly
+// what a pool sends as `job.data`: workers append the nonce and the hasher
+// appends the vdf solution.
+pub fn header_prefix(template: &BlockTemplate, merkle_root: &[u8; 32]) -> Result<String, String> {
+    let mut header = Vec::with_capacity(76);
+    header.extend_from_slice(&template.version.to_le_bytes());
+    header.extend_from_slice(&hash_from_rpc_hex(&template.previousblockhash)?);
+    header.extend_from_slice(merkle_root);
+    header.extend_from_slice(&template.curtime.to_le_bytes());
+    let bits = u32::from_str_radix(&template.bits, 16)
+        .map_err(|e| format!("invalid bits {}: {}", template.bits, e))?;
+    header.extend_from_slice(&bits.to_le_bytes());
+    Ok(hex::encode(header))
+}
+
+// Header prefix, nonce and vdf solution as produced by the workers,
+// followed by the transaction count, the coinbase and the template's
+// transactions.
+pub fn serialize_block(
+    header_prefix: &str,
+    nonce: &str,
+    path: &str,
+    coinbase: &str,
+    transactions: &[TemplateTransaction],
+) -> String {
+    let mut count = Vec::new();
+    write_varint(&mut count, transactions.len() as u64 + 1);
+
+    let mut block = String::with_capacity(
+        header_prefix.len() + nonce.len() + path.len() + coinbase.len()
+            + transactions.iter().map(|tx| tx.data.len()).sum::<usize>() + 18,
+    );
+    block.push_str(header_prefix);
+    block.push_str(nonce);
+    block.push_str(path);
+    block.push_str(&hex::encode(count));
+    block.push_str(coinbase);
+    for tx in transactions {
+        block.push_str(&tx.data);
+    }
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(previousblockhash: &str, version: u32, curtime: u32, bits: &str, height: u64) -> BlockTemplate {
+        BlockTemplate {
+            version,
+            previousblockhash: previousblockhash.to_string(),
+            transactions: Vec::new(),
+            coinbasevalue: 5_000_000_000,
+            default_witness_commitment: None,
+            target: "f".repeat(64),
+            curtime,
+            bits: bits.to_string(),
+            height,
+            longpollid: None,
+        }
+    }
+
+    fn rpc_hex(hash: [u8; 32]) -> String {
+        let mut hash = hash;
+        hash.reverse();
+        hex::encode(hash)
+    }
+
+    // Bitcoin's genesis block: a lone coinbase whose txid is the merkle root,
+    // and a header that hashes to the well-known genesis hash.
+    #[test]
+    fn genesis_header_hash() {
+        let genesis = template(&"0".repeat(64), 1, 1231006505, "1d00ffff", 0);
+        let coinbase_txid = hash_from_rpc_hex("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b").unwrap();
+        let root = template_merkle_root(&genesis, coinbase_txid).unwrap();
+        assert_eq!(root, coinbase_txid);
+
+        let prefix = header_prefix(&genesis, &root).unwrap();
+        assert_eq!(prefix.len(), 76 * 2);
+        let nonce = hex::encode(2083236893u32.to_le_bytes());
+        let header = hex::decode(format!("{}{}", prefix, nonce)).unwrap();
+        assert_eq!(rpc_hex(sha256d(&header)), "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
+    }
+
+    #[test]
+    fn block_100000_merkle_root() {
+        let mut block = template(&"0".repeat(64), 1, 0, "1b04864c", 100000);
+        block.transactions = [
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+        ]
+        .iter()
+        .map(|txid| TemplateTransaction { data: String::new(), txid: txid.to_string() })
+        .collect();
+        let coinbase_txid = hash_from_rpc_hex("8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87").unwrap();
+        let root = template_merkle_root(&block, coinbase_txid).unwrap();
+        assert_eq!(rpc_hex(root), "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766");
+    }
+
+    #[test]
+    fn bip34_height_pushes() {
+        for (height, expected) in [
+            (1, vec![0x51]),
+            (16, vec![0x60]),
+            (17, vec![0x01, 0x11]),
+            (128, vec![0x02, 0x80, 0x00]),
+            (32768, vec![0x03, 0x00, 0x80, 0x00]),
+        ] {
+            let mut script = Vec::new();
+            push_height(&mut script, height);
+            assert_eq!(script, expected, "height {}", height);
+        }
+    }
+
+    // The height push leads the coinbase scriptSig, right after the version,
+    // input count, null prevout and script length.
+    #[test]
+    fn coinbase_starts_with_height() {
+        let script_pubkey = [0x00, 0x14].iter().chain([0xab; 20].iter()).copied().collect::<Vec<u8>>();
+        let coinbase = build_coinbase(&template(&"0".repeat(64), 1, 0, "207fffff", 32768), &script_pubkey).unwrap();
+        let data = hex::decode(&coinbase.data).unwrap();
+        assert_eq!(&data[42..46], &[0x03, 0x00, 0x80, 0x00]);
+        assert_eq!(coinbase.txid, sha256d(&data));
+    }
+
+    #[test]
+    fn block_layout() {
+        let transactions = vec![TemplateTransaction { data: String::from("aa"), txid: "0".repeat(64) }];
+        let block = serialize_block("01", "02", "03", "04", &transactions);
+        assert_eq!(block, "0102030204aa");
+    }
+}
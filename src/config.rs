@@ -0,0 +1,645 @@
+use colored::*;
+use serde::Deserialize;
+use std::fmt;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use crate::address::{decode_address, MAINNET_HRP};
+use crate::affinity::CpuList;
+use crate::exit_codes;
+use crate::models::{Args, MineArgs, Percent, VdfTime};
+use crate::rpc::RpcClient;
+use crate::schedule::Schedule;
+use chrono_tz::Tz;
+
+pub const ENV_PREFIX: &str = "SHAIPOT_";
+
+pub const DEFAULT_SOLO_POLL: f64 = 5.0;
+pub const DEFAULT_BACKOFF_INITIAL: f64 = 5.0;
+pub const DEFAULT_BACKOFF_MAX: f64 = 300.0;
+pub const DEFAULT_KEEPALIVE: f64 = 30.0;
+pub const DEFAULT_IDLE_TIMEOUT: f64 = 90.0;
+pub const DEFAULT_API_BIND: &str = "127.0.0.1:8844";
+pub const DEFAULT_LOG_INTERVAL: f64 = 5.0;
+pub const DEFAULT_CALM_PERIOD: f64 = 60.0;
+// The bailout HCGraphUtil applies when no vdftime is given.
+pub const DEFAULT_VDFTIME_MS: u64 = 1000;
+
+// Settings a running miner picks up on SIGHUP; anything else needs a restart.
+pub const RELOADABLE: &[&str] = &["threads", "pools", "api_bind", "log_interval"];
+// Settings whose values `config check` does not print.
+const SECRET: &[&str] = &["api_token"];
+
+// The shape of `shaipot.toml`. Keys mirror the command line flags with
+// underscores instead of dashes, and every one of them is optional.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    address: Option<String>,
+    address_hrp: Option<String>,
+    pools: Option<Vec<String>>,
+    worker: Option<String>,
+    threads: Option<usize>,
+    cpus: Option<String>,
+    numa: Option<bool>,
+    background: Option<bool>,
+    max_cpu: Option<String>,
+    busy_threshold: Option<String>,
+    calm_period: Option<f64>,
+    schedule: Option<String>,
+    timezone: Option<String>,
+    vdftime: Option<FileVdfTime>,
+    solo: Option<String>,
+    solo_poll: Option<f64>,
+    backoff_initial: Option<f64>,
+    backoff_max: Option<f64>,
+    max_retries: Option<u32>,
+    keepalive: Option<f64>,
+    idle_timeout: Option<f64>,
+    job_timeout: Option<f64>,
+    api: Option<bool>,
+    api_bind: Option<SocketAddr>,
+    api_token: Option<String>,
+    log_interval: Option<f64>,
+}
+
+// `vdftime = 1.5` or `vdftime = "auto"`.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum FileVdfTime {
+    Seconds(f64),
+    Text(String),
+}
+
+// Where a resolved value came from. Later layers win:
+// default < config file < SHAIPOT_* environment < command line.
+#[derive(Clone, Debug)]
+pub enum Source {
+    Default,
+    File,
+    Env(String),
+    Cli(&'static str),
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File => write!(f, "config file"),
+            Source::Env(name) => write!(f, "env {}", name),
+            Source::Cli(flag) => write!(f, "{}", flag),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub key: &'static str,
+    pub value: String,
+    pub source: Source,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub file: Option<PathBuf>,
+    pub address: Option<String>,
+    pub address_hrp: String,
+    pub pools: Vec<String>,
+    pub worker: Option<String>,
+    pub threads: Option<usize>,
+    pub cpus: Option<CpuList>,
+    pub numa: bool,
+    pub background: bool,
+    pub max_cpu: Option<Percent>,
+    pub busy_threshold: Option<Percent>,
+    pub calm_period: f64,
+    pub schedule: Option<Schedule>,
+    pub timezone: Option<Tz>,
+    pub vdftime: Option<VdfTime>,
+    pub solo: Option<String>,
+    pub solo_poll: f64,
+    pub backoff_initial: f64,
+    pub backoff_max: f64,
+    pub max_retries: Option<u32>,
+    pub keepalive: f64,
+    pub idle_timeout: f64,
+    pub job_timeout: Option<f64>,
+    pub api: bool,
+    pub api_bind: SocketAddr,
+    pub api_token: Option<String>,
+    pub log_interval: f64,
+    pub entries: Vec<Entry>,
+}
+
+struct Resolver {
+    entries: Vec<Entry>,
+    errors: Vec<String>,
+}
+
+impl Resolver {
+    fn env<T: FromStr>(&mut self, key: &str) -> Option<(T, String)>
+    where
+        T::Err: Display,
+    {
+        let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+        let raw = std::env::var(&name).ok().filter(|raw| !raw.is_empty())?;
+        match raw.parse::<T>() {
+            Ok(value) => Some((value, name)),
+            Err(e) => {
+                self.errors.push(format!("{}={} is invalid: {}", name, raw, e));
+                None
+            }
+        }
+    }
+
+    fn optional<T: FromStr + Display>(
+        &mut self,
+        key: &'static str,
+        flag: &'static str,
+        file: Option<T>,
+        cli: Option<T>,
+    ) -> Option<T>
+    where
+        T::Err: Display,
+    {
+        let env = self.env::<T>(key);
+        let (value, source) = match (cli, env, file) {
+            (Some(value), _, _) => (Some(value), Source::Cli(flag)),
+            (None, Some((value, name)), _) => (Some(value), Source::Env(name)),
+            (None, None, Some(value)) => (Some(value), Source::File),
+            (None, None, None) => (None, Source::Default),
+        };
+        self.entries.push(Entry {
+            key,
+            value: value.as_ref().map_or_else(|| String::from("(not set)"), T::to_string),
+            source,
+        });
+        value
+    }
+
+    fn with_default<T: FromStr + Display>(
+        &mut self,
+        key: &'static str,
+        flag: &'static str,
+        default: T,
+        file: Option<T>,
+        cli: Option<T>,
+    ) -> T
+    where
+        T::Err: Display,
+    {
+        match self.optional(key, flag, file, cli) {
+            Some(value) => value,
+            None => {
+                if let Some(entry) = self.entries.last_mut() {
+                    entry.value = default.to_string();
+                }
+                default
+            }
+        }
+    }
+
+    // Lists come from a comma separated environment variable or a repeated flag.
+    fn list(&mut self, key: &'static str, flag: &'static str, file: Option<Vec<String>>, cli: Vec<String>) -> Vec<String> {
+        let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+        let env = std::env::var(&name).ok().map(|raw| {
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        });
+        let (value, source) = match (cli, env, file) {
+            (cli, _, _) if !cli.is_empty() => (cli, Source::Cli(flag)),
+            (_, Some(env), _) if !env.is_empty() => (env, Source::Env(name)),
+            (_, _, Some(file)) => (file, Source::File),
+            _ => (Vec::new(), Source::Default),
+        };
+        self.entries.push(Entry {
+            key,
+            value: if value.is_empty() { String::from("(not set)") } else { value.join(", ") },
+            source,
+        });
+        value
+    }
+}
+
+// A boolean set by a `--name` / `--no-name` flag pair, returning the flag
+// that was given and its value. clap keeps only the last of the two.
+fn switch(on: bool, off: bool, on_flag: &'static str, off_flag: &'static str) -> (&'static str, Option<bool>) {
+    match (on, off) {
+        (_, true) => (off_flag, Some(false)),
+        (true, false) => (on_flag, Some(true)),
+        (false, false) => (on_flag, None),
+    }
+}
+
+impl Config {
+    // Resolves every setting from the config file, the environment and the
+    // command line. All problems are collected so they can be reported at once.
+    pub fn resolve(args: &MineArgs) -> Result<Config, Vec<String>> {
+        let mut resolver = Resolver { entries: Vec::new(), errors: Vec::new() };
+
+        let file_path = args
+            .config
+            .clone()
+            .or_else(|| std::env::var_os(format!("{}CONFIG", ENV_PREFIX)).map(PathBuf::from));
+        let file = match &file_path {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(text) => match toml::from_str::<FileConfig>(&text) {
+                    Ok(file) => file,
+                    Err(e) => return Err(vec![format!("{} is not a valid config file: {}", path.display(), e)]),
+                },
+                Err(e) => return Err(vec![format!("Failed to read {}: {}", path.display(), e)]),
+            },
+            None => FileConfig::default(),
+        };
+
+        let file_vdftime = match file.vdftime.map(|vdftime| match vdftime {
+            FileVdfTime::Seconds(secs) => VdfTime::from_secs(secs),
+            FileVdfTime::Text(text) => text.parse::<VdfTime>(),
+        }) {
+            Some(Ok(vdftime)) => Some(vdftime),
+            Some(Err(e)) => {
+                resolver.errors.push(format!("vdftime in {}: {}", file_path.as_deref().unwrap_or(Path::new("")).display(), e));
+                None
+            }
+            None => None,
+        };
+
+        let file_cpus = match file.cpus.as_deref().map(str::parse::<CpuList>) {
+            Some(Ok(cpus)) => Some(cpus),
+            Some(Err(e)) => {
+                resolver.errors.push(format!("cpus in {}: {}", file_path.as_deref().unwrap_or(Path::new("")).display(), e));
+                None
+            }
+            None => None,
+        };
+
+        let file_max_cpu = match file.max_cpu.as_deref().map(str::parse::<Percent>) {
+            Some(Ok(max_cpu)) => Some(max_cpu),
+            Some(Err(e)) => {
+                resolver.errors.push(format!("max_cpu in {}: {}", file_path.as_deref().unwrap_or(Path::new("")).display(), e));
+                None
+            }
+            None => None,
+        };
+
+        let file_busy_threshold = match file.busy_threshold.as_deref().map(str::parse::<Percent>) {
+            Some(Ok(busy_threshold)) => Some(busy_threshold),
+            Some(Err(e)) => {
+                resolver.errors.push(format!("busy_threshold in {}: {}", file_path.as_deref().unwrap_or(Path::new("")).display(), e));
+                None
+            }
+            None => None,
+        };
+
+        let file_schedule = match file.schedule.as_deref().map(str::parse::<Schedule>) {
+            Some(Ok(schedule)) => Some(schedule),
+            Some(Err(e)) => {
+                resolver.errors.push(format!("schedule in {}: {}", file_path.as_deref().unwrap_or(Path::new("")).display(), e));
+                None
+            }
+            None => None,
+        };
+
+        let file_timezone = match file.timezone.as_deref().map(str::parse::<Tz>) {
+            Some(Ok(timezone)) => Some(timezone),
+            Some(Err(e)) => {
+                resolver.errors.push(format!("timezone in {}: {}", file_path.as_deref().unwrap_or(Path::new("")).display(), e));
+                None
+            }
+            None => None,
+        };
+
+        let r = &mut resolver;
+        let address = r.optional("address", "--address", file.address, args.address.clone());
+        let address_hrp = r
+            .with_default(
+                "address_hrp",
+                "--address-hrp",
+                String::from(MAINNET_HRP),
+                file.address_hrp.map(|hrp| hrp.to_lowercase()),
+                args.address_hrp.as_deref().map(str::to_lowercase),
+            )
+            .to_lowercase();
+        let pools = r.list("pools", "--pool", file.pools, args.pool.clone());
+        let worker = r.optional("worker", "--worker", file.worker, args.worker.clone());
+        let threads = r.optional("threads", "--threads", file.threads, args.threads);
+        let cpus = r.optional("cpus", "--cpus", file_cpus, args.cpus.clone());
+        let (numa_flag, numa_cli) = switch(args.numa, args.no_numa, "--numa", "--no-numa");
+        let numa = r.with_default("numa", numa_flag, false, file.numa, numa_cli);
+        let (background_flag, background_cli) = switch(args.background, args.no_background, "--background", "--no-background");
+        let background = r.with_default("background", background_flag, false, file.background, background_cli);
+        let max_cpu = r.optional("max_cpu", "--max-cpu", file_max_cpu, args.max_cpu);
+        let busy_threshold = r.optional("busy_threshold", "--busy-threshold", file_busy_threshold, args.busy_threshold);
+        let calm_period = r.with_default("calm_period", "--calm-period", DEFAULT_CALM_PERIOD, file.calm_period, args.calm_period);
+        let schedule = r.optional("schedule", "--schedule", file_schedule, args.schedule.clone());
+        let timezone = r.optional("timezone", "--timezone", file_timezone, args.timezone);
+        let vdftime = r.optional("vdftime", "--vdftime", file_vdftime, args.vdftime);
+        let solo = r.optional("solo", "--solo", file.solo, args.solo.clone());
+        let solo_poll = r.with_default("solo_poll", "--solo-poll", DEFAULT_SOLO_POLL, file.solo_poll, args.solo_poll);
+        let backoff_initial = r.with_default("backoff_initial", "--backoff-initial", DEFAULT_BACKOFF_INITIAL, file.backoff_initial, args.backoff_initial);
+        let backoff_max = r.with_default("backoff_max", "--backoff-max", DEFAULT_BACKOFF_MAX, file.backoff_max, args.backoff_max);
+        let max_retries = r.optional("max_retries", "--max-retries", file.max_retries, args.max_retries);
+        let keepalive = r.with_default("keepalive", "--keepalive", DEFAULT_KEEPALIVE, file.keepalive, args.keepalive);
+        let idle_timeout = r.with_default("idle_timeout", "--idle-timeout", DEFAULT_IDLE_TIMEOUT, file.idle_timeout, args.idle_timeout);
+        let job_timeout = r.optional("job_timeout", "--job-timeout", file.job_timeout, args.job_timeout);
+        let (api_flag, api_cli) = switch(args.api, args.no_api, "--api", "--no-api");
+        let api = r.with_default("api", api_flag, true, file.api, api_cli);
+        let api_bind = r.with_default("api_bind", "--api-bind", DEFAULT_API_BIND.parse().unwrap(), file.api_bind, args.api_bind);
+        let api_token = r.optional("api_token", "--api-token", file.api_token, args.api_token.clone());
+        let log_interval = r.with_default("log_interval", "--log-interval", DEFAULT_LOG_INTERVAL, file.log_interval, args.log_interval);
+
+        for pool in &pools {
+            if !pool.starts_with("ws://") && !pool.starts_with("wss://") {
+                resolver.errors.push(format!("Pool URL {} must start with ws:// or wss://", pool));
+            }
+        }
+        if let Some(Err(e)) = solo.as_deref().map(RpcClient::new) {
+            resolver.errors.push(e);
+        }
+
+        if api_token.as_deref().is_some_and(str::is_empty) {
+            resolver.errors.push(String::from("api_token must not be empty"));
+        }
+
+        if threads == Some(0) {
+            resolver.errors.push(String::from("threads must be at least 1"));
+        }
+
+        if let Some(address) = &address {
+            if let Err(e) = decode_address(address, &address_hrp) {
+                resolver.errors.push(e);
+            }
+        }
+
+        let positive = |secs: f64| secs > 0.0 && secs.is_finite();
+        for (key, secs) in [
+            ("solo_poll", Some(solo_poll)),
+            ("backoff_initial", Some(backoff_initial)),
+            ("backoff_max", Some(backoff_max)),
+            ("keepalive", Some(keepalive)),
+            ("idle_timeout", Some(idle_timeout)),
+            ("job_timeout", job_timeout),
+            ("log_interval", Some(log_interval)),
+            ("calm_period", Some(calm_period)),
+        ] {
+            if !secs.is_none_or(positive) {
+                resolver.errors.push(format!("{} must be a positive number of seconds", key));
+            }
+        }
+
+        if !resolver.errors.is_empty() {
+            return Err(resolver.errors);
+        }
+
+        Ok(Config {
+            file: file_path,
+            address,
+            address_hrp,
+            pools,
+            worker,
+            threads,
+            cpus,
+            numa,
+            background,
+            max_cpu,
+            busy_threshold,
+            calm_period,
+            schedule,
+            timezone,
+            vdftime,
+            solo,
+            solo_poll,
+            backoff_initial,
+            backoff_max,
+            max_retries,
+            keepalive,
+            idle_timeout,
+            job_timeout,
+            api,
+            api_bind,
+            api_token,
+            log_interval,
+            entries: resolver.entries,
+        })
+    }
+
+    // Resolves the configuration for mining, exiting on errors and showing
+    // the usage when there is nothing to mine with.
+    pub fn load(args: &MineArgs) -> Config {
+        let config = match Config::resolve(args) {
+            Ok(config) => config,
+            Err(errors) => {
+                for error in errors {
+                    println!("{}", error.bold().red());
+                }
+                std::process::exit(exit_codes::BAD_CONFIG);
+            }
+        };
+
+        if let Some(missing) = config.missing_required() {
+            println!("{}", missing.bold().red());
+            Args::show_demo_usage();
+            std::process::exit(exit_codes::BAD_CONFIG);
+        }
+
+        config
+    }
+
+    pub fn missing_required(&self) -> Option<&'static str> {
+        if self.address.is_none() {
+            return Some("address is not set");
+        }
+        if self.pools.is_empty() && self.solo.is_none() {
+            return Some("Neither pools nor solo is set");
+        }
+        None
+    }
+
+    // Keys whose resolved value differs between the two configurations.
+    pub fn changed_keys(&self, other: &Config) -> Vec<&'static str> {
+        self.entries
+            .iter()
+            .zip(&other.entries)
+            .filter(|(old, new)| old.value != new.value)
+            .map(|(old, _)| old.key)
+            .collect()
+    }
+
+    // Adopts the reloadable settings of `other`, except those in `fixed`
+    // that the current mode cannot apply. Everything else keeps the value
+    // the miner was started with, so it is reported again on the next
+    // reload until the miner is restarted.
+    pub fn reload_from(&mut self, other: &Config, fixed: &[&str]) {
+        let adopt = |key: &str| RELOADABLE.contains(&key) && !fixed.contains(&key);
+        if adopt("threads") {
+            self.threads = other.threads;
+        }
+        if adopt("pools") {
+            self.pools = other.pools.clone();
+        }
+        if adopt("api_bind") {
+            self.api_bind = other.api_bind;
+        }
+        if adopt("log_interval") {
+            self.log_interval = other.log_interval;
+        }
+        for (entry, new) in self.entries.iter_mut().zip(&other.entries) {
+            if adopt(entry.key) {
+                *entry = new.clone();
+            }
+        }
+    }
+
+    // The fixed bailout in milliseconds; None for the default and for auto.
+    pub fn vdftime_ms(&self) -> Option<u64> {
+        match self.vdftime {
+            Some(VdfTime::Seconds(secs)) => Some((secs * 1000.0) as u64),
+            _ => None,
+        }
+    }
+
+    pub fn vdftime_auto(&self) -> bool {
+        self.vdftime == Some(VdfTime::Auto)
+    }
+
+    pub fn print_sources(&self) {
+        match &self.file {
+            Some(path) => println!("{} {}", "Config file:".bold().cyan(), path.display().to_string().bold()),
+            None => println!("{} {}", "Config file:".bold().cyan(), "none".bright_black()),
+        }
+        let width = self.entries.iter().map(|entry| entry.key.len()).max().unwrap_or(0);
+        let value_width = self.entries.iter().map(|entry| entry.value.len()).max().unwrap_or(0);
+        for entry in &self.entries {
+            // Secrets are only shown as set or not.
+            let value = if SECRET.contains(&entry.key) && !matches!(entry.source, Source::Default) { "(hidden)" } else { entry.value.as_str() };
+            println!(
+                "  {:width$}  {:value_width$}  {}",
+                entry.key.bold(),
+                value,
+                format!("({})", entry.source).bright_black(),
+                width = width,
+                value_width = value_width,
+            );
+        }
+    }
+}
+
+// `shaipot config check`: print the resolved configuration with the source
+// of every value and report problems with a non-zero exit.
+pub fn check(args: &MineArgs) {
+    let config = match Config::resolve(args) {
+        Ok(config) => config,
+        Err(errors) => {
+            for error in errors {
+                println!("{}", error.bold().red());
+            }
+            std::process::exit(exit_codes::BAD_CONFIG);
+        }
+    };
+
+    config.print_sources();
+    if let Some(missing) = config.missing_required() {
+        println!("{}", missing.bold().red());
+        std::process::exit(exit_codes::BAD_CONFIG);
+    }
+    println!("{}", "Configuration OK".bold().green());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    const ADDRESS: &str = "sh1qeexkz69dz6j4q0zt0pkn36650yevwc8eksqeuu";
+
+    // Writes `toml` to a config file unique to the calling test.
+    fn config_file(name: &str, toml: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("shaipot-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, toml).unwrap();
+        path
+    }
+
+    fn resolve(args: &[&str]) -> Result<Config, Vec<String>> {
+        let args = Args::try_parse_from(std::iter::once("shaipot").chain(args.iter().copied())).unwrap();
+        Config::resolve(&args.mine_args())
+    }
+
+    fn source(config: &Config, key: &str) -> String {
+        config.entries.iter().find(|entry| entry.key == key).unwrap().source.to_string()
+    }
+
+    #[test]
+    fn later_layers_win() {
+        // A key of its own, so no other test sees the variable.
+        let mut resolver = Resolver { entries: Vec::new(), errors: Vec::new() };
+        assert_eq!(resolver.with_default("test_precedence", "--test-precedence", 1u32, None, None), 1);
+        assert_eq!(resolver.with_default("test_precedence", "--test-precedence", 1, Some(2), None), 2);
+        std::env::set_var("SHAIPOT_TEST_PRECEDENCE", "3");
+        assert_eq!(resolver.with_default("test_precedence", "--test-precedence", 1, Some(2), None), 3);
+        assert_eq!(resolver.with_default("test_precedence", "--test-precedence", 1, Some(2), Some(4)), 4);
+        std::env::remove_var("SHAIPOT_TEST_PRECEDENCE");
+
+        let sources: Vec<String> = resolver.entries.iter().map(|entry| entry.source.to_string()).collect();
+        assert_eq!(sources, ["default", "config file", "env SHAIPOT_TEST_PRECEDENCE", "--test-precedence"]);
+        assert!(resolver.errors.is_empty());
+    }
+
+    #[test]
+    fn invalid_env_value_is_reported() {
+        let mut resolver = Resolver { entries: Vec::new(), errors: Vec::new() };
+        std::env::set_var("SHAIPOT_TEST_INVALID", "many");
+        assert_eq!(resolver.with_default("test_invalid", "--test-invalid", 1u32, Some(2), None), 2);
+        std::env::remove_var("SHAIPOT_TEST_INVALID");
+        assert_eq!(resolver.errors.len(), 1);
+        assert!(resolver.errors[0].starts_with("SHAIPOT_TEST_INVALID=many is invalid"));
+    }
+
+    #[test]
+    fn file_and_command_line() {
+        let path = config_file("layers", &format!("address = \"{}\"\nthreads = 2\nkeepalive = 10.0\n", ADDRESS));
+        let config = resolve(&["--config", path.to_str().unwrap(), "--threads", "3"]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.threads, Some(3));
+        assert_eq!(source(&config, "threads"), "--threads");
+        assert_eq!(config.keepalive, 10.0);
+        assert_eq!(source(&config, "keepalive"), "config file");
+        assert_eq!(config.backoff_max, DEFAULT_BACKOFF_MAX);
+        assert_eq!(source(&config, "backoff_max"), "default");
+        assert_eq!(config.missing_required(), Some("Neither pools nor solo is set"));
+    }
+
+    #[test]
+    fn switch_pairs_override_the_file() {
+        let path = config_file("switches", "numa = true\nbackground = false\napi = false\n");
+        let file = path.to_str().unwrap();
+
+        let config = resolve(&["--config", file]).unwrap();
+        assert!(config.numa && !config.background && !config.api);
+        assert_eq!(source(&config, "numa"), "config file");
+
+        // The last flag of a pair wins.
+        let config = resolve(&["--config", file, "--numa", "--no-numa", "--no-background", "--background", "--api"]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!config.numa && config.background && config.api);
+        assert_eq!(source(&config, "numa"), "--no-numa");
+        assert_eq!(source(&config, "background"), "--background");
+        assert_eq!(source(&config, "api"), "--api");
+    }
+
+    #[test]
+    fn invalid_address_is_reported() {
+        // Reported along with the other problems rather than on its own.
+        let errors = resolve(&["--address", "sh1qinvalid", "--pool", "http://pool"]).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[1].starts_with("sh1qinvalid is not a valid bech32 address"), "{:?}", errors);
+
+        let errors = resolve(&["--address", ADDRESS, "--address-hrp", "TSH"]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].ends_with("is for network prefix 'sh' but 'tsh' was expected"), "{:?}", errors);
+    }
+}
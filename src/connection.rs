@@ -0,0 +1,416 @@
+use rand::Rng;
+use colored::*;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use crate::exit_codes;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Subscribed,
+    Backoff,
+    Failed,
+}
+
+impl ConnectionState {
+    pub const ALL: [ConnectionState; 5] = [
+        ConnectionState::Connecting,
+        ConnectionState::Connected,
+        ConnectionState::Subscribed,
+        ConnectionState::Backoff,
+        ConnectionState::Failed,
+    ];
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Subscribed => "subscribed",
+            ConnectionState::Backoff => "backoff",
+            ConnectionState::Failed => "failed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectionStatus {
+    pub state: ConnectionState,
+    pub since: u64,
+    pub failed_attempts: u32,
+    pub last_error: Option<String>,
+    pub retry_in: Option<u64>,
+    pub reconnects: u32,
+}
+
+impl ConnectionStatus {
+    pub fn new() -> Self {
+        ConnectionStatus {
+            state: ConnectionState::Connecting,
+            since: unix_time(),
+            failed_attempts: 0,
+            last_error: None,
+            retry_in: None,
+            reconnects: 0,
+        }
+    }
+}
+
+impl Default for ConnectionStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Drives the pool connection through its states, logging every transition
+// and mirroring it into the shared status served by the API.
+pub struct ConnectionTracker {
+    status: Arc<Mutex<ConnectionStatus>>,
+    backoff: Backoff,
+    max_retries: Option<u32>,
+    attempted: bool,
+}
+
+impl ConnectionTracker {
+    pub fn new(status: Arc<Mutex<ConnectionStatus>>, backoff: Backoff, max_retries: Option<u32>) -> Self {
+        ConnectionTracker { status, backoff, max_retries, attempted: false }
+    }
+
+    pub async fn state(&self) -> ConnectionState {
+        self.status.lock().await.state
+    }
+
+    async fn transition(&self, state: ConnectionState, error: Option<String>, retry_in: Option<Duration>) {
+        let mut status = self.status.lock().await;
+        let previous = status.state;
+        status.state = state;
+        status.since = unix_time();
+        status.failed_attempts = self.backoff.attempts();
+        status.retry_in = retry_in.map(|d| d.as_secs());
+        if error.is_some() {
+            status.last_error = error.clone();
+        }
+
+        if previous != state {
+            let line = match &error {
+                Some(e) => format!("Connection: {} -> {} ({})", previous, state, e),
+                None => format!("Connection: {} -> {}", previous, state),
+            };
+            match state {
+                ConnectionState::Backoff | ConnectionState::Failed => println!("{}", line.red()),
+                _ => println!("{}", line.bright_black()),
+            }
+        }
+    }
+
+    pub async fn connecting(&mut self) {
+        if self.attempted {
+            self.status.lock().await.reconnects += 1;
+        }
+        self.attempted = true;
+        self.transition(ConnectionState::Connecting, None, None).await;
+    }
+
+    pub async fn connected(&self) {
+        self.transition(ConnectionState::Connected, None, None).await;
+    }
+
+    // The pool only counts as healthy once it hands out work, so that is
+    // also the point where the backoff starts over.
+    pub async fn subscribed(&mut self) {
+        if self.state().await == ConnectionState::Subscribed {
+            return;
+        }
+        self.backoff.reset();
+        self.transition(ConnectionState::Subscribed, None, None).await;
+    }
+
+    pub async fn failed(&self, error: String) {
+        self.transition(ConnectionState::Failed, Some(error), None).await;
+    }
+
+    // Returns the delay to wait before the next attempt, or None once the
+    // retry budget is spent and the tracker has moved to `Failed`.
+    pub async fn backoff(&mut self, error: String) -> Option<Duration> {
+        let delay = self.backoff.next_delay();
+        if let Some(max_retries) = self.max_retries {
+            if self.backoff.attempts() > max_retries {
+                self.failed(error).await;
+                return None;
+            }
+        }
+        self.transition(ConnectionState::Backoff, Some(error), Some(delay)).await;
+        Some(delay)
+    }
+}
+
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max: max.max(initial),
+            attempts: 0,
+        }
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    // Doubles per consecutive failure up to the cap, then picks a random
+    // point in the upper half so a fleet behind one pool does not reconnect
+    // in lockstep.
+    pub fn next_delay(&mut self) -> Duration {
+        let exp = self.attempts.min(31);
+        self.attempts = self.attempts.saturating_add(1);
+
+        let ceiling = self
+            .initial
+            .checked_mul(1u32 << exp)
+            .unwrap_or(self.max)
+            .min(self.max);
+        let floor = ceiling / 2;
+        if ceiling <= floor {
+            return ceiling;
+        }
+        rand::thread_rng().gen_range(floor..=ceiling)
+    }
+}
+
+// Application close codes a pool can use to tell the miner that
+// reconnecting will not help. Anything else is treated as routine.
+pub const CLOSE_BANNED: u16 = 4001;
+pub const CLOSE_INVALID_ADDRESS: u16 = 4002;
+pub const CLOSE_OUTDATED_MINER: u16 = 4003;
+
+pub struct PoolClose {
+    pub code: Option<u16>,
+    pub reason: String,
+    pub exit_code: Option<i32>,
+}
+
+impl PoolClose {
+    pub fn from_frame(frame: Option<&CloseFrame<'_>>) -> Self {
+        let (code, reason) = match frame {
+            Some(frame) => (Some(u16::from(frame.code)), frame.reason.trim().to_string()),
+            None => (None, String::new()),
+        };
+        let lowered = reason.to_lowercase();
+
+        let exit_code = match code {
+            Some(CLOSE_BANNED) => Some(exit_codes::POOL_BANNED),
+            Some(CLOSE_INVALID_ADDRESS) => Some(exit_codes::POOL_INVALID_ADDRESS),
+            Some(CLOSE_OUTDATED_MINER) => Some(exit_codes::POOL_OUTDATED_MINER),
+            // Older pools only send a policy violation with a human readable reason.
+            Some(1008) if lowered.contains("banned") => Some(exit_codes::POOL_BANNED),
+            Some(1008) if lowered.contains("invalid address") => Some(exit_codes::POOL_INVALID_ADDRESS),
+            _ => None,
+        };
+
+        PoolClose { code, reason, exit_code }
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        self.exit_code.is_some()
+    }
+}
+
+impl fmt::Display for PoolClose {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.code, self.reason.is_empty()) {
+            (Some(code), false) => write!(f, "pool closed the connection ({}: {})", code, self.reason),
+            (Some(code), true) => write!(f, "pool closed the connection ({})", code),
+            (None, _) => write!(f, "pool closed the connection"),
+        }
+    }
+}
+
+pub enum KeepaliveTimeout {
+    Idle(Duration),
+    StaleJob(Duration),
+}
+
+impl fmt::Display for KeepaliveTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeepaliveTimeout::Idle(d) => write!(f, "nothing received from pool for {} seconds", d.as_secs()),
+            KeepaliveTimeout::StaleJob(d) => write!(f, "no new job from pool for {} seconds", d.as_secs()),
+        }
+    }
+}
+
+// Tracks liveness of a single pool session. A half-open TCP connection
+// never errors on read, so silence has to be detected by the miner itself.
+pub struct Keepalive {
+    idle_timeout: Duration,
+    job_timeout: Option<Duration>,
+    last_frame: Instant,
+    last_job: Instant,
+    ping_sent_at: Option<Instant>,
+}
+
+impl Keepalive {
+    pub fn new(idle_timeout: Duration, job_timeout: Option<Duration>) -> Self {
+        let now = Instant::now();
+        Keepalive {
+            idle_timeout,
+            job_timeout,
+            last_frame: now,
+            last_job: now,
+            ping_sent_at: None,
+        }
+    }
+
+    pub fn frame_received(&mut self) {
+        self.last_frame = Instant::now();
+    }
+
+    pub fn job_received(&mut self) {
+        self.last_job = Instant::now();
+    }
+
+    pub fn ping_sent(&mut self) {
+        self.ping_sent_at = Some(Instant::now());
+    }
+
+    // Returns the round trip time of the outstanding ping, if any.
+    pub fn pong_received(&mut self) -> Option<Duration> {
+        self.ping_sent_at.take().map(|sent| sent.elapsed())
+    }
+
+    pub fn check(&self) -> Option<KeepaliveTimeout> {
+        let idle = self.last_frame.elapsed();
+        if idle > self.idle_timeout {
+            return Some(KeepaliveTimeout::Idle(idle));
+        }
+        if let Some(job_timeout) = self.job_timeout {
+            let stale = self.last_job.elapsed();
+            if stale > job_timeout {
+                return Some(KeepaliveTimeout::StaleJob(stale));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+    fn close(code: u16, reason: &str) -> PoolClose {
+        let frame = CloseFrame { code: CloseCode::from(code), reason: reason.to_string().into() };
+        PoolClose::from_frame(Some(&frame))
+    }
+
+    #[test]
+    fn delay_doubles_within_the_upper_half() {
+        let initial = Duration::from_millis(100);
+        let mut backoff = Backoff::new(initial, Duration::from_secs(60));
+        for attempt in 0..6 {
+            let ceiling = initial * (1 << attempt);
+            let delay = backoff.next_delay();
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "attempt {}: {:?}", attempt, delay);
+        }
+        assert_eq!(backoff.attempts(), 6);
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let max = Duration::from_secs(5);
+        let mut backoff = Backoff::new(Duration::from_secs(1), max);
+        // Far past the point where the doubling would overflow.
+        for _ in 0..100 {
+            let delay = backoff.next_delay();
+            assert!(delay <= max, "{:?}", delay);
+        }
+        let delay = backoff.next_delay();
+        assert!(delay >= max / 2);
+    }
+
+    #[test]
+    fn max_below_initial_is_raised() {
+        let mut backoff = Backoff::new(Duration::from_secs(4), Duration::from_secs(1));
+        let delay = backoff.next_delay();
+        assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(4));
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let initial = Duration::from_millis(100);
+        let mut backoff = Backoff::new(initial, Duration::from_secs(60));
+        for _ in 0..5 {
+            backoff.next_delay();
+        }
+        backoff.reset();
+        assert_eq!(backoff.attempts(), 0);
+        assert!(backoff.next_delay() <= initial);
+    }
+
+    #[tokio::test]
+    async fn subscribing_resets_the_backoff() {
+        let status = Arc::new(Mutex::new(ConnectionStatus::new()));
+        let backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(10));
+        let mut tracker = ConnectionTracker::new(status.clone(), backoff, Some(2));
+
+        tracker.connecting().await;
+        assert!(tracker.backoff("refused".to_string()).await.is_some());
+        tracker.connecting().await;
+        assert!(tracker.backoff("refused".to_string()).await.is_some());
+        assert_eq!(status.lock().await.failed_attempts, 2);
+
+        tracker.connecting().await;
+        tracker.connected().await;
+        tracker.subscribed().await;
+        assert_eq!(status.lock().await.failed_attempts, 0);
+
+        // The retry budget is whole again after a healthy session.
+        assert!(tracker.backoff("closed".to_string()).await.is_some());
+        assert!(tracker.backoff("refused".to_string()).await.is_some());
+        assert!(tracker.backoff("refused".to_string()).await.is_none());
+        assert_eq!(tracker.state().await, ConnectionState::Failed);
+        assert_eq!(status.lock().await.reconnects, 2);
+    }
+
+    #[test]
+    fn pool_close_codes_map_to_exit_codes() {
+        assert_eq!(close(CLOSE_BANNED, "").exit_code, Some(exit_codes::POOL_BANNED));
+        assert_eq!(close(CLOSE_INVALID_ADDRESS, "").exit_code, Some(exit_codes::POOL_INVALID_ADDRESS));
+        assert_eq!(close(CLOSE_OUTDATED_MINER, "update").exit_code, Some(exit_codes::POOL_OUTDATED_MINER));
+        assert!(!close(1012, "restart").is_fatal());
+        assert!(!PoolClose::from_frame(None).is_fatal());
+    }
+
+    #[test]
+    fn policy_violation_falls_back_to_the_reason() {
+        assert_eq!(close(1008, " You are BANNED ").exit_code, Some(exit_codes::POOL_BANNED));
+        assert_eq!(close(1008, "Invalid address").exit_code, Some(exit_codes::POOL_INVALID_ADDRESS));
+        assert!(!close(1008, "too many connections").is_fatal());
+        assert_eq!(close(1008, " You are BANNED ").reason, "You are BANNED");
+    }
+}
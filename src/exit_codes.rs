@@ -0,0 +1,18 @@
+// Process exit codes, kept distinct so service managers and orchestration
+// can tell a routine stop apart from a condition that needs a human.
+
+// Invalid flags, config file or environment; clap uses the same code for
+// usage errors.
+pub const BAD_CONFIG: i32 = 2;
+// Gave up on the pool or node after --max-retries failed attempts.
+pub const CONNECTION_FAILED: i32 = 3;
+// Could not listen on the stats API or proxy address, usually because the
+// port is already taken.
+pub const BIND_FAILED: i32 = 4;
+
+// The pool closed the connection in a way that reconnecting cannot fix.
+pub const POOL_BANNED: i32 = 20;
+pub const POOL_INVALID_ADDRESS: i32 = 21;
+pub const POOL_OUTDATED_MINER: i32 = 22;
+// The pool answered the login with login_rejected.
+pub const POOL_LOGIN_REJECTED: i32 = 23;
@@ -1,4 +1,3 @@
-use hex;
 use primitive_types::U256;
 use sha2::{Digest, Sha256};
 use super::vdf_solution::{HCGraphUtil, GRAPH_SIZE};
@@ -115,3 +114,32 @@ pub fn compute_hash_no_vdf_verify(data: &str, hc_util: &mut HCGraphUtil) -> Opti
 
     Some((final_hash_reversed, vdf_solution_hex_solved))
 }
+
+// Verifies a submitted solution for `data` (header prefix plus nonce) and
+// returns the resulting hash if the path is a valid Hamiltonian cycle.
+pub fn verify_solution(data: &str, path_hex: &str, hc_util: &HCGraphUtil) -> Option<String> {
+    let vdf_solution_hex: String = "ffff".repeat(GRAPH_SIZE.into());
+    let data_bytes = hex::decode(format!("{}{}", data, vdf_solution_hex)).ok()?;
+
+    let hash1 = Sha256::digest(&data_bytes);
+    let hash1_reversed = hex::encode(hash1.iter().rev().cloned().collect::<Vec<u8>>());
+    let graph_hash_u256 = U256::from_str_radix(&hash1_reversed, 16).unwrap();
+
+    let path_bytes = hex::decode(path_hex).ok()?;
+    if path_bytes.len() != usize::from(GRAPH_SIZE) * 2 {
+        return None;
+    }
+    let path: Vec<u16> = path_bytes
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+
+    if !hc_util.verify_hamiltonian_cycle(graph_hash_u256, &path) {
+        return None;
+    }
+
+    let data_bytes_solved = hex::decode(format!("{}{}", data, path_hex)).ok()?;
+    let hash2 = Sha256::digest(&data_bytes_solved);
+
+    Some(hex::encode(hash2.iter().rev().cloned().collect::<Vec<u8>>()))
+}
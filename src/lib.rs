@@ -0,0 +1,22 @@
+pub mod vdf_solution;
+pub mod ascii_art;
+pub mod models;
+pub mod hasher;
+pub mod utils;
+pub mod api;
+pub mod config;
+pub mod connection;
+pub mod exit_codes;
+pub mod block;
+pub mod address;
+pub mod rpc;
+pub mod solo;
+pub mod proxy;
+pub mod worker;
+pub mod tools;
+pub mod affinity;
+pub mod numa;
+pub mod load;
+pub mod schedule;
+pub mod metrics;
+pub mod vdftune;
@@ -0,0 +1,230 @@
+// Watches how much CPU the rest of the machine wants so workers can step
+// aside for it: `/proc/stat` gives the CPU time other processes got, and the
+// runnable count in `/proc/loadavg` shows demand that is still waiting for a core.
+use serde::Serialize;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+pub const MAX_EVENTS: usize = 20;
+
+pub struct LoadSampler {
+    cpus: usize,
+    last_total: u64,
+    last_busy: u64,
+    last_own: u64,
+}
+
+impl LoadSampler {
+    pub fn new() -> Result<LoadSampler, String> {
+        let (cpus, total, busy) = read_system_times()?;
+        let own = read_own_time()?;
+        Ok(LoadSampler { cpus, last_total: total, last_busy: busy, last_own: own })
+    }
+
+    pub fn cpus(&self) -> usize {
+        self.cpus
+    }
+
+    // CPUs worth of demand from processes other than the miner: the CPU time
+    // they used since the previous sample, or how many of them are runnable
+    // right now if that is more.
+    pub fn sample(&mut self) -> Result<f64, String> {
+        let (_, total, busy) = read_system_times()?;
+        let own = read_own_time()?;
+        let total_delta = total.saturating_sub(self.last_total);
+        let other_delta = busy.saturating_sub(self.last_busy).saturating_sub(own.saturating_sub(self.last_own));
+        self.last_total = total;
+        self.last_busy = busy;
+        self.last_own = own;
+
+        let used = if total_delta == 0 { 0.0 } else { other_delta as f64 / total_delta as f64 * self.cpus as f64 };
+        let runnable = read_runnable()?.saturating_sub(count_own_runnable());
+        Ok(used.max(runnable as f64))
+    }
+}
+
+// CPU count plus total and busy clock ticks summed over all CPUs.
+fn read_system_times() -> Result<(usize, u64, u64), String> {
+    let text = std::fs::read_to_string("/proc/stat").map_err(|e| format!("cannot read /proc/stat: {}", e))?;
+    let cpus = text.lines().filter(|line| line.starts_with("cpu") && !line.starts_with("cpu ")).count();
+    let fields: Vec<u64> = text
+        .lines()
+        .find(|line| line.starts_with("cpu "))
+        .ok_or_else(|| String::from("/proc/stat has no cpu line"))?
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|field| field.parse().ok())
+        .collect();
+    if fields.len() < 5 {
+        return Err(String::from("/proc/stat has a short cpu line"));
+    }
+    // user nice system idle iowait irq softirq steal; guest time is
+    // already counted in user and nice.
+    let total: u64 = fields.iter().take(8).sum();
+    let busy = total - fields[3] - fields[4];
+    Ok((cpus.max(1), total, busy))
+}
+
+// User plus system clock ticks used by this process, all threads included.
+fn read_own_time() -> Result<u64, String> {
+    let text = std::fs::read_to_string("/proc/self/stat").map_err(|e| format!("cannot read /proc/self/stat: {}", e))?;
+    // The command name is in parentheses and may itself contain spaces.
+    let after_name = text.rsplit_once(')').map(|(_, rest)| rest).unwrap_or("");
+    let fields: Vec<&str> = after_name.split_whitespace().collect();
+    let parse = |index: usize| fields.get(index).and_then(|field| field.parse::<u64>().ok());
+    match (parse(11), parse(12)) {
+        (Some(utime), Some(stime)) => Ok(utime + stime),
+        _ => Err(String::from("/proc/self/stat has an unexpected format")),
+    }
+}
+
+// Threads on the whole machine that are running or waiting for a CPU. The
+// load averages themselves lag by minutes, so the current count is used.
+fn read_runnable() -> Result<usize, String> {
+    let text = std::fs::read_to_string("/proc/loadavg").map_err(|e| format!("cannot read /proc/loadavg: {}", e))?;
+    text.split_whitespace()
+        .nth(3)
+        .and_then(|field| field.split_once('/'))
+        .and_then(|(runnable, _)| runnable.parse().ok())
+        .ok_or_else(|| String::from("/proc/loadavg has an unexpected format"))
+}
+
+// Threads of this process that are running or runnable, the sampling thread included.
+fn count_own_runnable() -> usize {
+    let Ok(tasks) = std::fs::read_dir("/proc/self/task") else {
+        return 0;
+    };
+    tasks
+        .flatten()
+        .filter_map(|task| std::fs::read_to_string(task.path().join("stat")).ok())
+        .filter(|stat| stat.rsplit_once(')').is_some_and(|(_, rest)| rest.trim_start().starts_with('R')))
+        .count()
+}
+
+#[derive(Serialize, Clone)]
+pub struct LoadEvent {
+    pub time: u64,
+    pub action: &'static str,
+    pub workers: usize,
+    pub other_cpus: f64,
+}
+
+// Decides how many workers may run given the demand of other processes.
+// Workers are only scaled down while the machine is busy and all of them
+// come back once it has stayed calm for `calm_period`.
+pub struct LoadGovernor {
+    cpus: usize,
+    threshold: f64,
+    calm_period: Duration,
+    limit: Option<usize>,
+    calm_since: Option<Instant>,
+}
+
+impl LoadGovernor {
+    // `threshold` is the share of the machine, between 0 and 1, other
+    // processes must use before workers make room.
+    pub fn new(cpus: usize, threshold: f64, calm_period: Duration) -> LoadGovernor {
+        LoadGovernor { cpus, threshold, calm_period, limit: None, calm_since: None }
+    }
+
+    pub fn allowed(&self, configured: usize) -> usize {
+        self.limit.map_or(configured, |limit| limit.min(configured))
+    }
+
+    // Feeds one sample and returns an event when the allowed count changes.
+    pub fn update(&mut self, other_cpus: f64, configured: usize) -> Option<LoadEvent> {
+        let current = self.allowed(configured);
+        if other_cpus >= self.threshold * self.cpus as f64 {
+            self.calm_since = None;
+            let room = self.cpus.saturating_sub(other_cpus.ceil() as usize);
+            if room < current {
+                self.limit = Some(room);
+                let action = if room == 0 { "pause" } else { "scale_down" };
+                return Some(event(action, room, other_cpus));
+            }
+            return None;
+        }
+
+        self.limit?;
+        let calm_since = *self.calm_since.get_or_insert_with(Instant::now);
+        if calm_since.elapsed() < self.calm_period {
+            return None;
+        }
+        self.limit = None;
+        self.calm_since = None;
+        Some(event("resume", configured, other_cpus))
+    }
+}
+
+fn event(action: &'static str, workers: usize, other_cpus: f64) -> LoadEvent {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    LoadEvent { time, action, workers, other_cpus: (other_cpus * 100.0).round() / 100.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn quiet_machine_changes_nothing() {
+        let mut governor = LoadGovernor::new(8, 0.5, HOUR);
+        assert!(governor.update(3.9, 8).is_none());
+        assert!(governor.update(0.0, 8).is_none());
+        assert_eq!(governor.allowed(8), 8);
+    }
+
+    #[test]
+    fn busy_machine_scales_down_then_pauses() {
+        let mut governor = LoadGovernor::new(8, 0.5, HOUR);
+        let down = governor.update(5.2, 8).unwrap();
+        assert_eq!((down.action, down.workers), ("scale_down", 2));
+        assert_eq!(governor.allowed(8), 2);
+        // Less demand while still busy does not give workers back.
+        assert!(governor.update(4.0, 8).is_none());
+        assert_eq!(governor.allowed(8), 2);
+
+        let pause = governor.update(7.5, 8).unwrap();
+        assert_eq!((pause.action, pause.workers), ("pause", 0));
+        assert_eq!(governor.allowed(8), 0);
+    }
+
+    #[test]
+    fn room_above_the_configured_count_is_no_change() {
+        let mut governor = LoadGovernor::new(8, 0.5, HOUR);
+        assert!(governor.update(4.0, 2).is_none());
+        assert_eq!(governor.allowed(2), 2);
+    }
+
+    #[test]
+    fn resume_waits_for_the_calm_period() {
+        let mut governor = LoadGovernor::new(4, 0.5, HOUR);
+        governor.update(3.0, 4).unwrap();
+        assert!(governor.update(0.5, 4).is_none());
+        assert!(governor.update(0.0, 4).is_none());
+        assert_eq!(governor.allowed(4), 1);
+
+        let mut governor = LoadGovernor::new(4, 0.5, Duration::ZERO);
+        governor.update(3.0, 4).unwrap();
+        let resume = governor.update(0.5, 4).unwrap();
+        assert_eq!((resume.action, resume.workers), ("resume", 4));
+        assert_eq!(governor.allowed(4), 4);
+    }
+
+    #[test]
+    fn busy_sample_restarts_the_calm_timer() {
+        let calm_period = Duration::from_millis(300);
+        let mut governor = LoadGovernor::new(4, 0.5, calm_period);
+        governor.update(3.0, 4).unwrap();
+        assert!(governor.update(0.0, 4).is_none());
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(governor.update(2.0, 4).is_none());
+        assert!(governor.update(0.0, 4).is_none());
+        std::thread::sleep(Duration::from_millis(200));
+        // 400ms since the first calm sample, but only 200ms since the last busy one.
+        assert!(governor.update(0.0, 4).is_none());
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(governor.update(0.0, 4).unwrap().action, "resume");
+    }
+}
@@ -18,142 +18,389 @@
 // Care about the emission. It’s freedom in code.
 // Just a pulse in the network, a chance to be heard.
 //
-mod vdf_solution;
-mod ascii_art;
-mod models;
-mod hasher;
-mod utils;
-mod api;
-
-use utils::*;
-use models::*;
-use hasher::*;
-use rand::Rng;
+use shaipot::{address, api, config, exit_codes, load, numa, proxy, rpc, solo, tools};
+use shaipot::load::{LoadGovernor, LoadSampler};
+use shaipot::schedule::MiningSchedule;
+use shaipot::vdftune::{self, VdfTuner};
+use chrono::Utc;
+use shaipot::config::{Config, DEFAULT_VDFTIME_MS};
+use clap::Parser;
+use shaipot::utils::*;
+use shaipot::models::*;
+use primitive_types::U256;
 use colored::*;
-use std::thread;
-use ascii_art::*;
+use shaipot::ascii_art::*;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::{Mutex};
-use crate::api::MinerState;
-use vdf_solution::HCGraphUtil;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::collections::VecDeque;
+use tokio::sync::{watch, Mutex};
+use tokio::sync::mpsc::unbounded_channel;
+use shaipot::api::{MinerState, NodeCounters, Payout, ScheduleStatus, SearchRates, WorkerStats};
+use shaipot::connection::{Backoff, ConnectionTracker, Keepalive, KeepaliveTimeout, PoolClose};
+use shaipot::worker::{Placement, WorkerContext, WorkerPool};
 use futures_util::{StreamExt, SinkExt};
-use std::sync::{atomic::{AtomicUsize, Ordering}, mpsc};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 
+// How often the console lists every worker's counters.
+const WORKER_SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+// How long a finished session waits for its close frame to be written.
+const SESSION_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[tokio::main]
 async fn main() {
-    let args = Args::parse_and_validate();
+    let args = Args::parse();
     std::panic::set_hook(Box::new(|_info| {}));
 
-    let max_workers = num_cpus::get();
-    assert!(max_workers > 0);
-
-    let num_workers = match args.threads {
-        Some(t) => {
-            if t >= max_workers {
-                println!("{}", "Requested number of threads exceeds available cores. Using maximum allowed".bold().red());
-                max_workers
-            } else {
-                t
-            }
+    match &args.command {
+        Some(Command::Proxy(proxy_args)) => {
+            print_startup_art();
+            tokio::spawn(handle_exit_signals());
+            proxy::run_proxy(proxy_args.clone()).await;
+            return;
         }
-        None => max_workers,
-    };
+        Some(Command::Config { action: ConfigCommand::Check(mine_args) }) => {
+            config::check(mine_args);
+            return;
+        }
+        Some(Command::Bench(bench_args)) => {
+            tools::run_bench(bench_args);
+            return;
+        }
+        Some(Command::Verify(verify_args)) => {
+            tools::run_verify(verify_args);
+            return;
+        }
+        Some(Command::Solve(solve_args)) => {
+            tools::run_solve(solve_args);
+            return;
+        }
+        Some(Command::Mine(_)) | None => {}
+    }
+
+    let args = args.mine_args();
+    let config = Config::load(&args);
+
+    let placement = worker_placement(&config);
+    let num_workers = worker_count(config.threads, &placement);
 
     println!("{}", "STARTING MINER".bold().green());
     println!("{} {}", "USING WORKERS: ".bold().cyan(), format!("{}", num_workers).bold().cyan());
+    if config.background {
+        println!("{}", "BACKGROUND MODE: workers run at nice 19 / SCHED_IDLE".bold().cyan());
+    }
+    if let (Some(schedule), timezone) = (&config.schedule, config.timezone) {
+        let schedule = MiningSchedule::new(schedule.clone(), timezone);
+        println!("{} {}", "MINING SCHEDULE:".bold().cyan(), schedule.to_string().bold().cyan());
+    }
+    if config.vdftime_auto() {
+        println!("{} {}", "VDFTIME:".bold().cyan(), format!("auto, starting at {} ms", DEFAULT_VDFTIME_MS).bold().cyan());
+    }
+    if let Some(max_cpu) = config.max_cpu {
+        println!("{} {}", "CPU LIMIT PER WORKER:".bold().cyan(), max_cpu.to_string().bold().cyan());
+    }
+    match &placement {
+        Placement::Unpinned => {}
+        Placement::Cpus(cpus) => println!("{} {}", "PINNED TO CPUS:".bold().cyan(), cpus.to_string().bold().cyan()),
+        Placement::Numa(nodes) => {
+            let nodes: Vec<String> = nodes.iter().map(|node| format!("node{} ({})", node.id, node.cpus)).collect();
+            println!("{} {}", "NUMA NODES:".bold().cyan(), nodes.join(", ").bold().cyan());
+        }
+    }
     print_startup_art();
 
     // Handle Ctrl+C signal
     tokio::spawn(handle_exit_signals());
+    let (reload_sender, mut reload_receiver) = unbounded_channel();
+    tokio::spawn(handle_reload_signal(reload_sender));
 
-    let bailout_timer = args.vdftime_parsed;
-    let miner_id = args.address.unwrap();
+    let bailout_timer = config.vdftime_ms();
+    let miner_id = config.address.clone().unwrap();
 
-    let (server_sender, server_receiver) = mpsc::channel::<String>();
+    let (server_sender, mut server_receiver) = unbounded_channel::<String>();
 
     let current_job: Arc<Mutex<Option<Job>>> = Arc::new(Mutex::new(None));
 
     let miner_state = Arc::new(MinerState {
-        hash_count: Arc::new(AtomicUsize::new(0)),
-        accepted_shares: Arc::new(AtomicUsize::new(0)),
-        rejected_shares: Arc::new(AtomicUsize::new(0)),
-        hashrate_samples: Arc::new(Mutex::new(Vec::new())),
-        version: String::from("1.0.0"),
+        configured_workers: Arc::new(AtomicUsize::new(num_workers)),
+        numa_nodes: Arc::new(match &placement {
+            Placement::Numa(nodes) => nodes
+                .iter()
+                .map(|node| NodeCounters {
+                    node: node.id,
+                    cpus: node.cpus.to_string(),
+                    workers: AtomicUsize::new(0),
+                    hash_count: AtomicUsize::new(0),
+                    hashrate: AtomicUsize::new(0),
+                })
+                .collect(),
+            _ => Vec::new(),
+        }),
+        vdftime_ms: Arc::new(AtomicU64::new(bailout_timer.unwrap_or(DEFAULT_VDFTIME_MS))),
+        vdf_tuner: config.vdftime_auto().then(|| Arc::new(VdfTuner::new(DEFAULT_VDFTIME_MS))),
+        ..MinerState::default()
     });
 
+    // The API is bound before any work starts so that a taken port stops the
+    // miner, and is moved whenever a reload changes its address.
+    let (api_bind_sender, mut api_bind_receiver) = watch::channel(config.api_bind);
+    if config.api {
+        let api_state = miner_state.clone();
+        let api_token = config.api_token.clone();
+        let mut current_bind = config.api_bind;
+        let mut server = match api::bind_http_server(api_state.clone(), current_bind, api_token.clone()) {
+            Ok(server) => tokio::spawn(server),
+            Err(e) => {
+                println!("{}", e.bold().red());
+                std::process::exit(exit_codes::BIND_FAILED);
+            }
+        };
+        let auth = if api_token.is_some() { " (bearer token required)" } else { "" };
+        println!("{} {}{}", "STATS API:".bold().cyan(), format!("http://{}/stats", current_bind).bold().cyan(), auth);
+        tokio::spawn(async move {
+            while api_bind_receiver.changed().await.is_ok() {
+                let bind = *api_bind_receiver.borrow_and_update();
+                server.abort();
+                let _ = (&mut server).await;
+                let (bound, restarted) = match api::bind_http_server(api_state.clone(), bind, api_token.clone()) {
+                    Ok(restarted) => (bind, restarted),
+                    Err(e) => {
+                        println!("{}", format!("{}, keeping it on {}.", e, current_bind).red());
+                        match api::bind_http_server(api_state.clone(), current_bind, api_token.clone()) {
+                            Ok(restarted) => (current_bind, restarted),
+                            Err(e) => {
+                                println!("{}", e.red());
+                                continue;
+                            }
+                        }
+                    }
+                };
+                current_bind = bound;
+                server = tokio::spawn(restarted);
+            }
+        });
+    }
+
+    // With --vdftime auto, re-pick the bailout from the solve times seen so far.
+    if let Some(tuner) = miner_state.vdf_tuner.clone() {
+        let tuner_state = miner_state.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + vdftune::EVALUATE_INTERVAL, vdftune::EVALUATE_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let previous = tuner.evaluate();
+                tuner_state.vdftime_ms.store(tuner.chosen_ms(), Ordering::Relaxed);
+                if let (Some(previous), Some(status)) = (previous, tuner.status()) {
+                    println!("{}", format!("vdftime auto: {} ms -> {} ms ({})", previous, status.chosen_ms, status.reason).cyan());
+                }
+            }
+        });
+    }
+
     // Spawn worker threads for processing jobs
     let hash_count = Arc::new(AtomicUsize::new(0));
-    for _ in 0..num_workers {
-        let current_job_loop = Arc::clone(&current_job);
-        let hash_count = Arc::clone(&hash_count);
-        let server_sender_clone = server_sender.clone();
-        let  miner_id = miner_id.clone();
-        let api_hash_count = Arc::clone(&miner_state.hash_count);
-
-        thread::spawn(move || {
-            let mut hc_util = HCGraphUtil::new(bailout_timer);
-            let mut hc_util_verify = HCGraphUtil::new(bailout_timer);
-            loop {
-                let job_option = {
-                    let job_guard = current_job_loop.blocking_lock();
-                    job_guard.clone()
-                };
+    let mut workers = WorkerPool::new(WorkerContext {
+        current_job: Arc::clone(&current_job),
+        submissions: server_sender,
+        miner_id: miner_id.clone(),
+        miner_state: Arc::clone(&miner_state),
+        hash_count: Arc::clone(&hash_count),
+        bailout_timer,
+        background: config.background,
+        max_cpu: config.max_cpu.map(|max_cpu| max_cpu.0 / 100.0),
+        node: None,
+    }, placement.clone());
+    let mut load_watch = match config.busy_threshold {
+        Some(threshold) => match LoadSampler::new() {
+            Ok(sampler) => {
+                let calm_period = Duration::from_secs_f64(config.calm_period);
+                let governor = LoadGovernor::new(sampler.cpus(), threshold.0 / 100.0, calm_period);
+                Some((sampler, governor))
+            }
+            Err(e) => {
+                println!("{}", format!("Cannot watch the machine load, ignoring --busy-threshold: {}", e).yellow());
+                None
+            }
+        },
+        None => None,
+    };
+    let schedule = config.schedule.clone().map(|schedule| MiningSchedule::new(schedule, config.timezone));
 
-                if let Some(job) = job_option {
-                    loop {
-                        let nonce = generate_nonce();
-
-                        if let Some((hash, path_hex)) = compute_hash_no_vdf(&("".to_owned() + &job.data + &nonce), &mut hc_util) {
-                            hash_count.fetch_add(1, Ordering::Relaxed);
-                            api_hash_count.fetch_add(1, Ordering::Relaxed);
-
-                            if meets_target(&hash, &job.target) {
-                                if let Some((_hash_v, _path_hex_v)) = compute_hash_no_vdf_verify(&("".to_owned() + &job.data + &nonce), &mut hc_util_verify) {
-                                    if meets_target(&hash, &job.target) {
-                                        let submit_msg = SubmitMessage {
-                                            r#type: String::from("submit"),
-                                            miner_id: miner_id.to_string(),
-                                            nonce: nonce,
-                                            job_id: job.job_id.clone(),
-                                            path: path_hex,
-                                        };
-        
-                                        let msg = serde_json::to_string(&submit_msg).unwrap();
-                                        let _ = server_sender_clone.send(msg);
-        
-                                        let mut job_guard = current_job_loop.blocking_lock();
-                                        *job_guard = None;
-                                        break;
-                                    }
-                                }
-                            }
+    // The supervisor owns the worker pool: it runs the configured number of
+    // workers, none outside the mining schedule, and fewer while other
+    // processes keep the machine busy.
+    let supervisor_state = miner_state.clone();
+    let calm_period = config.calm_period;
+    let (threads_sender, mut threads_receiver) = watch::channel(num_workers);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + load::SAMPLE_INTERVAL, load::SAMPLE_INTERVAL);
+        let mut in_window = schedule.as_ref().is_none_or(|schedule| schedule.is_open(Utc::now()));
+        let mut next_transition = None;
+        if let Some(schedule) = &schedule {
+            next_transition = schedule.next_transition(Utc::now());
+            if !in_window {
+                match next_transition {
+                    Some(at) => println!("{}", format!("Outside the mining schedule, workers idle until {}.", schedule.format(at)).yellow()),
+                    None => println!("{}", "Outside the mining schedule.".yellow()),
+                }
+            }
+        }
 
-                            // Check if there's a new job
-                            let new_job_option = {
-                                let job_guard = current_job_loop.blocking_lock();
-                                job_guard.clone()
-                            };
+        loop {
+            let configured = *threads_receiver.borrow();
+            supervisor_state.configured_workers.store(configured, Ordering::Relaxed);
+            let allowed = match &load_watch {
+                _ if !in_window => 0,
+                Some((_, governor)) => governor.allowed(configured),
+                None => configured,
+            };
+            if workers.size() != allowed {
+                workers.resize(allowed);
+            }
+            if let Some(schedule) = &schedule {
+                *supervisor_state.schedule.lock().await = Some(ScheduleStatus {
+                    schedule: schedule.to_string(),
+                    mining: in_window,
+                    next_transition: next_transition.map(|at| schedule.to_rfc3339(at)),
+                });
+            }
+            let until_transition = next_transition.map(|at| (at - Utc::now()).to_std().unwrap_or_default());
 
-                            if new_job_option.is_none() || new_job_option.unwrap().job_id != job.job_id {
-                                break;
-                            }
+            tokio::select! {
+                changed = threads_receiver.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+                _ = tokio::time::sleep(until_transition.unwrap_or_default()), if until_transition.is_some() => {
+                    let Some(schedule) = &schedule else {
+                        continue;
+                    };
+                    let now = Utc::now();
+                    // The wall clock may lag the timer slightly; wait again
+                    // until the schedule really changes.
+                    if schedule.is_open(now) == in_window {
+                        next_transition = schedule.next_transition(now);
+                        continue;
+                    }
+                    in_window = !in_window;
+                    next_transition = schedule.next_transition(now);
+                    let until = next_transition.map_or_else(String::new, |at| format!(" until {}", schedule.format(at)));
+                    if in_window {
+                        println!("{}", format!("Mining window open{}.", until).green());
+                    } else {
+                        println!("{}", format!("Mining window closed, workers idle{}.", until).yellow());
+                    }
+                }
+                _ = ticker.tick(), if load_watch.is_some() && in_window => {
+                    let Some((sampler, governor)) = load_watch.as_mut() else {
+                        continue;
+                    };
+                    let other_cpus = match sampler.sample() {
+                        Ok(other_cpus) => other_cpus,
+                        Err(e) => {
+                            println!("{}", format!("Failed to sample the machine load: {}", e).red());
+                            continue;
+                        }
+                    };
+                    *supervisor_state.other_cpus.lock().await = (other_cpus * 100.0).round() / 100.0;
+                    let configured = *threads_receiver.borrow();
+                    if let Some(event) = governor.update(other_cpus, configured) {
+                        match event.action {
+                            "pause" => println!(
+                                "{}",
+                                format!("Machine busy ({:.1} CPUs used by other processes), pausing all workers.", other_cpus).yellow()
+                            ),
+                            "resume" => println!(
+                                "{}",
+                                format!("Machine calm for {} seconds, resuming {} workers.", calm_period, event.workers).green()
+                            ),
+                            _ => println!(
+                                "{}",
+                                format!("Machine busy ({:.1} CPUs used by other processes), scaling down to {} workers.", other_cpus, event.workers).yellow()
+                            ),
+                        }
+                        let mut events = supervisor_state.load_events.lock().await;
+                        events.push_back(event);
+                        if events.len() > load::MAX_EVENTS {
+                            events.pop_front();
                         }
                     }
                 }
             }
-        });
-    }
+        }
+    });
 
     // Spawn hash rate monitoring task
+    let monitor_state = miner_state.clone();
+    let (log_interval_sender, log_interval_receiver) = watch::channel(config.log_interval);
     tokio::spawn(async move {
         let mut last_count = 0;
+        let (mut last_attempts, mut last_bailouts) = (0, 0);
+        let mut last_node_counts = vec![0; monitor_state.numa_nodes.len()];
+        let mut last_worker_counts: Vec<usize> = Vec::new();
+        let mut last_summary = Instant::now();
         loop {
-            tokio::time::sleep(Duration::from_secs(5)).await;
+            let log_interval = *log_interval_receiver.borrow();
+            tokio::time::sleep(Duration::from_secs_f64(log_interval)).await;
             let count = hash_count.load(Ordering::Relaxed);
-            println!("{}: {} hashes/second", "Hash rate".cyan(), (count - last_count) / 5);
+            let hashrate = ((count - last_count) as f64 / log_interval) as usize;
+            let attempts = monitor_state.attempts.load(Ordering::Relaxed);
+            let bailouts = monitor_state.bailouts.load(Ordering::Relaxed);
+            let rates = SearchRates {
+                attempts_per_second: (attempts - last_attempts) as f64 / log_interval,
+                solved_per_second: (count - last_count) as f64 / log_interval,
+                bailout_ratio: if attempts == last_attempts {
+                    0.0
+                } else {
+                    (bailouts - last_bailouts) as f64 / (attempts - last_attempts) as f64
+                },
+            };
+            *monitor_state.search_rates.lock().await = rates;
+            (last_attempts, last_bailouts) = (attempts, bailouts);
+            let searches = format!(
+                "{:.2} graph attempts/s, {:.2} solved/s, {:.1}% bailouts",
+                rates.attempts_per_second,
+                rates.solved_per_second,
+                rates.bailout_ratio * 100.0
+            );
+            let pool_hashrate = monitor_state.pool_status.lock().await.hashrate;
+            match pool_hashrate {
+                Some(pool_hashrate) => println!(
+                    "{}: {} hashes/second ({}; pool reports {:.2})",
+                    "Hash rate".cyan(),
+                    hashrate,
+                    searches,
+                    pool_hashrate
+                ),
+                None => println!("{}: {} hashes/second ({})", "Hash rate".cyan(), hashrate, searches),
+            }
+            for (node, last_node_count) in monitor_state.numa_nodes.iter().zip(last_node_counts.iter_mut()) {
+                let node_count = node.hash_count.load(Ordering::Relaxed);
+                let node_hashrate = ((node_count - *last_node_count) as f64 / log_interval) as usize;
+                node.hashrate.store(node_hashrate, Ordering::Relaxed);
+                println!(
+                    "  {} {}: {} hashes/second ({} workers)",
+                    "node".cyan(),
+                    node.node,
+                    node_hashrate,
+                    node.workers.load(Ordering::Relaxed)
+                );
+                *last_node_count = node_count;
+            }
+            let worker_counters = monitor_state.worker_counters.lock().unwrap_or_else(|e| e.into_inner()).clone();
+            last_worker_counts.resize(worker_counters.len(), 0);
+            for (worker, last_worker_count) in worker_counters.iter().zip(last_worker_counts.iter_mut()) {
+                let worker_count = worker.hash_count.load(Ordering::Relaxed);
+                worker.hashrate.store(((worker_count - *last_worker_count) as f64 / log_interval) as usize, Ordering::Relaxed);
+                *last_worker_count = worker_count;
+            }
+            if last_summary.elapsed() >= WORKER_SUMMARY_INTERVAL {
+                last_summary = Instant::now();
+                print_worker_summary(&monitor_state.worker_stats());
+                print_solve_times(&monitor_state);
+            }
             last_count = count;
         }
     });
@@ -175,49 +422,233 @@ async fn main() {
         }
     });
 
-    let api_state = miner_state.clone();
-    tokio::spawn(api::start_http_server(api_state));
+    let (pools_sender, mut pools_receiver) = watch::channel(config.pools.clone());
+
+    // SIGHUP re-reads the configuration and applies what can change at runtime.
+    let reload_state = miner_state.clone();
+    let mut reload_config = config.clone();
+    // Reloadable settings that this mode has no use for.
+    let fixed: Vec<&str> = [config.solo.is_some().then_some("pools"), (!config.api).then_some("api_bind")]
+        .into_iter()
+        .flatten()
+        .collect();
+    tokio::spawn(async move {
+        while reload_receiver.recv().await.is_some() {
+            println!("{}", "SIGHUP received, reloading configuration...".bold().cyan());
+            let new_config = match Config::resolve(&args) {
+                Ok(new_config) => new_config,
+                Err(errors) => {
+                    for error in errors {
+                        println!("{}", error.red());
+                    }
+                    println!("{}", "Reload failed, keeping the current configuration.".bold().red());
+                    continue;
+                }
+            };
+            if let Some(missing) = new_config.missing_required() {
+                println!("{}", format!("Reload failed, keeping the current configuration: {}.", missing).bold().red());
+                continue;
+            }
+
+            let changed = reload_config.changed_keys(&new_config);
+            if changed.is_empty() {
+                println!("{}", "Configuration unchanged.".cyan());
+            }
+            for key in changed {
+                match key {
+                    "threads" => {
+                        let count = worker_count(new_config.threads, &placement);
+                        let configured = reload_state.configured_workers.load(Ordering::Relaxed);
+                        println!("{} {} -> {}", "Workers:".bold().cyan(), configured, count);
+                        let _ = threads_sender.send(count);
+                    }
+                    "pools" if fixed.contains(&"pools") => {
+                        println!("{}", "pools changed but is not applied (solo mode).".yellow());
+                    }
+                    "pools" => {
+                        println!("{} {}", "Reconnecting with pools:".bold().cyan(), new_config.pools.join(", "));
+                        let _ = pools_sender.send(new_config.pools.clone());
+                    }
+                    "api_bind" if fixed.contains(&"api_bind") => {
+                        println!("{}", "api_bind changed but is not applied (API disabled).".yellow());
+                    }
+                    "api_bind" => {
+                        println!("{} {}", "Moving API to".bold().cyan(), new_config.api_bind);
+                        let _ = api_bind_sender.send(new_config.api_bind);
+                    }
+                    "log_interval" => {
+                        println!("{} {} seconds", "Hash rate log interval:".bold().cyan(), new_config.log_interval);
+                        let _ = log_interval_sender.send(new_config.log_interval);
+                    }
+                    key => {
+                        println!("{}", format!("{} cannot change while running, restart the miner to apply it.", key).yellow());
+                    }
+                }
+            }
+            reload_config.reload_from(&new_config, &fixed);
+        }
+    });
+
+    let backoff = Backoff::new(
+        Duration::from_secs_f64(config.backoff_initial),
+        Duration::from_secs_f64(config.backoff_max),
+    );
+    let mut connection = ConnectionTracker::new(miner_state.connection.clone(), backoff, config.max_retries);
+
+    if let Some(rpc_url) = config.solo.as_deref() {
+        let rpc = match rpc::RpcClient::new(rpc_url) {
+            Ok(rpc) => rpc,
+            Err(e) => {
+                println!("{}", e.bold().red());
+                std::process::exit(exit_codes::BAD_CONFIG);
+            }
+        };
+        let payout = match address::decode_address(&miner_id, &config.address_hrp) {
+            Ok(payout) => payout,
+            Err(e) => {
+                println!("{}", e.bold().red());
+                std::process::exit(exit_codes::BAD_CONFIG);
+            }
+        };
+        let poll_interval = Duration::from_secs_f64(config.solo_poll);
+        solo::run_solo(rpc, payout.script_pubkey(), current_job, server_receiver, miner_state, connection, poll_interval).await;
+        return;
+    }
 
     let current_job_clone = Arc::clone(&current_job);
-    let request_clone = args.pool.unwrap().clone();
+    let mut pool_index = 0;
 
-    let server_receiver = Arc::new(Mutex::new(server_receiver));
+    // Submissions a session could not send; the next one sends them first.
+    let mut unsent: VecDeque<String> = VecDeque::new();
+    let keepalive_interval = Duration::from_secs_f64(config.keepalive);
+    let idle_timeout = Duration::from_secs_f64(config.idle_timeout);
+    let job_timeout = config.job_timeout.map(Duration::from_secs_f64);
 
     loop {
-        let request = request_clone.clone().into_client_request().unwrap();
+        connection.connecting().await;
+        let pools = pools_receiver.borrow_and_update().clone();
+        pool_index %= pools.len();
+        let pool = &pools[pool_index];
+        let request = match pool.as_str().into_client_request() {
+            Ok(request) => request,
+            Err(e) => {
+                println!("{}", format!("Invalid pool URL {}: {}", pool, e).bold().red());
+                std::process::exit(exit_codes::BAD_CONFIG);
+            }
+        };
         let (ws_stream, _) = match connect_async(request).await {
             Ok((ws_stream, response)) => {
                 (ws_stream, response)
             }
-            Err(_e) => {
-                let delay_secs = rand::thread_rng().gen_range(5..30);
-                println!("{}", format!("Failed to connect will retry in {} seconds...", delay_secs).red());
-                tokio::time::sleep(Duration::from_secs(delay_secs)).await;
+            Err(e) => {
+                let Some(delay) = connection.backoff(format!("connect to {} failed: {}", pool, e)).await else {
+                    println!("{}", "Giving up after reaching --max-retries.".bold().red());
+                    std::process::exit(exit_codes::CONNECTION_FAILED);
+                };
+                pool_index = next_pool(&pools, pool_index);
+                println!("{}", format!("Failed to connect will retry in {:.1} seconds...", delay.as_secs_f64()).red());
+                tokio::time::sleep(delay).await;
                 continue;
             }
         };
+        connection.connected().await;
 
-        let (write, mut read) = ws_stream.split();
+        // The session owns both halves of the socket and writes submissions
+        // itself, so nothing outlives it and holds on to a dead connection.
+        let (mut write, mut read) = ws_stream.split();
 
-        // Spawn write task to send solutions to the server
-        let server_receiver_clone = Arc::clone(&server_receiver);
-        tokio::spawn(async move {
-            let mut write = write;
-            while let Ok(msg) = {
-                let receiver = server_receiver_clone.lock().await;
-                receiver.recv()
-            } {
-                write.send(Message::Text(msg)).await.unwrap();
-            }
-        });
-        
+        let login_msg = LoginMessage {
+            r#type: String::from("login"),
+            miner_id: miner_id.to_string(),
+            worker: config.worker.clone(),
+            agent: user_agent(),
+            version: String::from(MINER_VERSION),
+            threads: miner_state.workers.load(Ordering::Relaxed),
+            features: SUPPORTED_FEATURES.iter().map(|f| f.to_string()).collect(),
+        };
+        let login_msg = serde_json::to_string(&login_msg).unwrap();
+        if let Err(e) = write.send(Message::Text(login_msg)).await {
+            let Some(delay) = connection.backoff(format!("login failed: {}", e)).await else {
+                println!("{}", "Giving up after reaching --max-retries.".bold().red());
+                std::process::exit(exit_codes::CONNECTION_FAILED);
+            };
+            println!("{}", format!("Failed to log in will retry in {:.1} seconds...", delay.as_secs_f64()).red());
+            tokio::time::sleep(delay).await;
+            continue;
+        }
+
+        let mut keepalive = Keepalive::new(idle_timeout, job_timeout);
+        let mut ping_timer = tokio::time::interval_at(tokio::time::Instant::now() + keepalive_interval, keepalive_interval);
+
+        if !unsent.is_empty() {
+            println!("{}", format!("Sending {} share(s) found while disconnected.", unsent.len()).yellow());
+        }
+
+        let disconnect_reason;
+        let mut pools_changed = false;
         loop {
-            match read.next().await {
+            if let Some(msg) = unsent.pop_front() {
+                if let Err(e) = write.send(Message::Text(msg.clone())).await {
+                    unsent.push_front(msg);
+                    println!("{}", "WebSocket connection closed. Will sleep then try to reconnect.".red());
+                    disconnect_reason = format!("submit failed: {}", e);
+                    break;
+                }
+                continue;
+            }
+            let next = tokio::select! {
+                next = read.next() => next,
+                Some(msg) = server_receiver.recv() => {
+                    if let Err(e) = write.send(Message::Text(msg.clone())).await {
+                        unsent.push_back(msg);
+                        println!("{}", "WebSocket connection closed. Will sleep then try to reconnect.".red());
+                        disconnect_reason = format!("submit failed: {}", e);
+                        break;
+                    }
+                    continue;
+                }
+                Ok(()) = pools_receiver.changed() => {
+                    println!("{}", "Pool list changed, reconnecting.".yellow());
+                    disconnect_reason = String::from("pool list changed");
+                    pools_changed = true;
+                    break;
+                }
+                _ = ping_timer.tick() => {
+                    if let Some(timeout) = keepalive.check() {
+                        match timeout {
+                            KeepaliveTimeout::Idle(_) => miner_state.idle_timeouts.fetch_add(1, Ordering::Relaxed),
+                            KeepaliveTimeout::StaleJob(_) => miner_state.stale_job_timeouts.fetch_add(1, Ordering::Relaxed),
+                        };
+                        println!("{}", format!("Dropping pool connection: {}.", timeout).red());
+                        disconnect_reason = timeout.to_string();
+                        break;
+                    }
+                    if let Err(e) = write.send(Message::Ping(Vec::new())).await {
+                        println!("{}", "WebSocket connection closed. Will sleep then try to reconnect.".red());
+                        disconnect_reason = format!("ping failed: {}", e);
+                        break;
+                    }
+                    keepalive.ping_sent();
+                    miner_state.pings_sent.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            };
+
+            match next {
                 Some(Ok(msg)) => {
+                    keepalive.frame_received();
                     match msg {
                         Message::Text(text_msg) => {
-                            let server_message: ServerMessage =
-                                serde_json::from_str(&text_msg).unwrap();
+                            let server_message: ServerMessage = match serde_json::from_str(&text_msg) {
+                                Ok(server_message) => server_message,
+                                Err(e) => {
+                                    println!("{}", format!("Ignoring malformed message from pool: {}", e).red());
+                                    continue;
+                                }
+                            };
+                            if let Some(score) = server_message.pplns_score {
+                                miner_state.pool_status.lock().await.pplns_score = Some(score);
+                            }
                             match server_message.r#type.as_str() {
                                 "job" => {
                                     if let (Some(job_id), Some(data), Some(target)) = (
@@ -225,14 +656,23 @@ async fn main() {
                                         server_message.data.clone(),
                                         server_message.target.clone(),
                                     ) {
+                                        if U256::from_str_radix(&target, 16).is_err() {
+                                            println!("{}", format!("Ignoring job {} with an invalid target from pool.", job_id).red());
+                                            continue;
+                                        }
                                         let new_job = Job {
                                             job_id: job_id.clone(),
                                             data: data.clone(),
                                             target: target.clone(),
+                                            nonce_range: server_message.nonce_start.zip(server_message.nonce_count),
                                         };
         
                                         let mut job_guard = current_job_clone.lock().await;
                                         *job_guard = Some(new_job);
+                                        drop(job_guard);
+                                        *miner_state.difficulty.lock().await = target_to_difficulty(&target);
+                                        keepalive.job_received();
+                                        connection.subscribed().await;
         
                                         println!(
                                             "{} {}",
@@ -246,30 +686,128 @@ async fn main() {
                                         );
                                     }
                                 }
+                                "login_accepted" => {
+                                    let name = config.worker.as_deref().unwrap_or(&miner_id);
+                                    println!("{} {}", "Logged in to pool as".bold().green(), name.bold().cyan());
+                                    if let Some(message) = server_message.message {
+                                        println!("{}", message.green());
+                                    }
+                                }
+                                "login_rejected" => {
+                                    let message = server_message.message.unwrap_or_else(|| String::from("no reason given"));
+                                    connection.failed(format!("login rejected: {}", message)).await;
+                                    println!("{}", format!("Pool rejected login: {}. Reconnecting will not help, exiting.", message).bold().red());
+                                    std::process::exit(exit_codes::POOL_LOGIN_REJECTED);
+                                }
+                                "set_target" | "set_difficulty" => {
+                                    let target = match (server_message.target, server_message.difficulty) {
+                                        (Some(target), _) => Some(target),
+                                        (None, Some(difficulty)) => difficulty_to_target(difficulty),
+                                        (None, None) => None,
+                                    };
+                                    let Some(target) = target.filter(|t| U256::from_str_radix(t, 16).is_ok()) else {
+                                        println!("{}", "Ignoring invalid share target from pool.".red());
+                                        continue;
+                                    };
+
+                                    let difficulty = target_to_difficulty(&target);
+                                    *miner_state.difficulty.lock().await = difficulty;
+                                    if let Some(job) = current_job_clone.lock().await.as_mut() {
+                                        job.target = target.clone();
+                                    }
+                                    println!(
+                                        "{} {}",
+                                        "New share difficulty:".bold().blue(),
+                                        format!("{:.2} (Target = {})", difficulty, target).bold().yellow()
+                                    );
+                                }
                                 "accepted" => {
                                     miner_state.accepted_shares.fetch_add(1, Ordering::Relaxed);
+                                    if let Some(pending) = miner_state.pending_shares.lock().await.pop_front() {
+                                        *miner_state.accepted_work.lock().await += pending.difficulty;
+                                        miner_state.submit_latency.observe(pending.submitted.elapsed());
+                                    }
                                     display_share_accepted();
                                 }
                                 "rejected" => {
                                     miner_state.rejected_shares.fetch_add(1, Ordering::Relaxed);
+                                    if let Some(pending) = miner_state.pending_shares.lock().await.pop_front() {
+                                        miner_state.submit_latency.observe(pending.submitted.elapsed());
+                                    }
                                     println!("{}", "Share rejected.".red());
                                 }
-                                _ => {}
+                                "pplns_score" => {
+                                    if let Some(score) = server_message.pplns_score {
+                                        println!("{} {}", "PPLNS score:".bold().cyan(), format!("{:.4}", score).bold().yellow());
+                                    }
+                                }
+                                "hashrate" => {
+                                    if let Some(hashrate) = server_message.hashrate {
+                                        miner_state.pool_status.lock().await.hashrate = Some(hashrate);
+                                        println!("{}: {:.2} hashes/second", "Pool-side hash rate".cyan(), hashrate);
+                                    }
+                                }
+                                "balance" => {
+                                    if let Some(balance) = server_message.balance {
+                                        miner_state.pool_status.lock().await.balance = Some(balance);
+                                        println!("{} {}", "Pool balance:".bold().cyan(), format!("{:.8} SHA", balance).bold().yellow());
+                                    }
+                                }
+                                "payout" => {
+                                    if let Some(amount) = server_message.amount {
+                                        let txid = server_message.txid.clone();
+                                        println!(
+                                            "{} {}",
+                                            "Payout sent:".bold().green(),
+                                            format!("{:.8} SHA {}", amount, txid.as_deref().unwrap_or("")).bold().yellow()
+                                        );
+                                        miner_state.pool_status.lock().await.last_payout = Some(Payout { amount, txid });
+                                    }
+                                }
+                                "notice" => {
+                                    if let Some(message) = server_message.message {
+                                        println!("{} {}", "Pool notice:".bold().yellow(), message);
+                                        miner_state.pool_status.lock().await.last_notice = Some(message);
+                                    }
+                                }
+                                "error" => {
+                                    let message = server_message.message.unwrap_or_else(|| String::from("unspecified error"));
+                                    println!("{} {}", "Pool error:".bold().red(), message.red());
+                                    miner_state.pool_status.lock().await.last_error = Some(message);
+                                }
+                                other => {
+                                    println!("{}", format!("Ignoring unknown pool message type '{}'", other).bright_black());
+                                }
                             }
                         }
-                        Message::Close(_) => {
-                            println!("{}", "You are now a frog.".green());
-                            std::process::exit(0);
+                        Message::Pong(_) => {
+                            miner_state.pongs_received.fetch_add(1, Ordering::Relaxed);
+                            if let Some(rtt) = keepalive.pong_received() {
+                                miner_state.ping_rtt_ms.store(rtt.as_millis() as usize, Ordering::Relaxed);
+                            }
+                        }
+                        Message::Close(frame) => {
+                            let close = PoolClose::from_frame(frame.as_ref());
+                            if close.is_fatal() {
+                                connection.failed(close.to_string()).await;
+                                println!("{}", format!("{}. Reconnecting will not help, exiting.", close).bold().red());
+                                std::process::exit(close.exit_code.unwrap());
+                            }
+                            println!("{}", format!("{}. Will sleep then try to reconnect.", close).yellow());
+                            disconnect_reason = close.to_string();
+                            break;
                         }
                         _ => {}
                     }
                 }
-                Some(Err(_e)) => {
+                Some(Err(e)) => {
                     println!("{}", "WebSocket connection closed. Will sleep then try to reconnect.".red());
+                    disconnect_reason = format!("read failed: {}", e);
                     break;
                 }
                 None => {
                     println!("{}", "WebSocket connection closed. Will sleep then try to reconnect.".red());
+                    disconnect_reason = String::from("stream ended");
                     break;
                 }
             }
@@ -277,10 +815,124 @@ async fn main() {
 
         let mut job_guard = current_job_clone.lock().await;
         *job_guard = None;
+        drop(job_guard);
 
-        let delay_secs = rand::thread_rng().gen_range(11..42);
-        println!("{}", format!("Reconnecting in {} seconds...", delay_secs).yellow());
-        tokio::time::sleep(Duration::from_secs(delay_secs)).await;
+        // Shares still waiting to be written go to the next session. Those
+        // already sent will not be answered, so only the unsent ones stay pending.
+        while let Ok(msg) = server_receiver.try_recv() {
+            unsent.push_back(msg);
+        }
+        {
+            let mut pending_shares = miner_state.pending_shares.lock().await;
+            let sent = pending_shares.len().saturating_sub(unsent.len());
+            pending_shares.drain(..sent);
+        }
+        let _ = tokio::time::timeout(SESSION_CLOSE_TIMEOUT, write.close()).await;
+
+        if pools_changed {
+            pool_index = 0;
+            continue;
+        }
+
+        let Some(delay) = connection.backoff(disconnect_reason).await else {
+            println!("{}", "Giving up after reaching --max-retries.".bold().red());
+            std::process::exit(exit_codes::CONNECTION_FAILED);
+        };
+        pool_index = next_pool(&pools, pool_index);
+        println!("{}", format!("Reconnecting in {:.1} seconds...", delay.as_secs_f64()).yellow());
+        tokio::time::sleep(delay).await;
         println!("{}", "Attempting to reconnect...".red());
     }
-}
\ No newline at end of file
+}
+
+// Pools are tried in the order they are configured, moving on to the next
+// one whenever a connection attempt or session fails.
+fn next_pool(pools: &[String], index: usize) -> usize {
+    let next = (index + 1) % pools.len();
+    if next != index {
+        println!("{} {}", "Switching to pool".bold().yellow(), pools[next].bold().cyan());
+    }
+    next
+}
+
+fn worker_placement(config: &Config) -> Placement {
+    if !config.numa {
+        return config.cpus.clone().map_or(Placement::Unpinned, Placement::Cpus);
+    }
+    match numa::detect_nodes() {
+        Ok(nodes) => {
+            let nodes = match &config.cpus {
+                Some(cpus) => numa::restrict_nodes(nodes, cpus),
+                None => nodes,
+            };
+            if nodes.is_empty() {
+                println!("{}", "None of the CPUs in --cpus belong to a NUMA node.".bold().red());
+                std::process::exit(exit_codes::BAD_CONFIG);
+            }
+            Placement::Numa(nodes)
+        }
+        Err(e) => {
+            println!("{}", format!("NUMA topology unavailable ({}), placing workers without it.", e).yellow());
+            config.cpus.clone().map_or(Placement::Unpinned, Placement::Cpus)
+        }
+    }
+}
+
+// One line per running worker, so a stuck, throttled or misplaced thread
+// stands out against the others.
+fn print_worker_summary(workers: &[WorkerStats]) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    println!("{}", "Worker summary:".cyan());
+    for worker in workers.iter().filter(|worker| worker.running) {
+        let cpu = match (&worker.cpus, worker.last_cpu) {
+            (Some(cpus), Some(last_cpu)) => format!("cpu {}, pinned to {}", last_cpu, cpus),
+            (None, Some(last_cpu)) => format!("cpu {}", last_cpu),
+            (_, None) => String::from("cpu ?"),
+        };
+        let bailout_ratio = if worker.attempts == 0 { 0.0 } else { worker.bailouts as f64 / worker.attempts as f64 * 100.0 };
+        let line = format!(
+            "  worker {} ({}): {} hashes/second, {} attempts, {:.1}% bailouts, avg solve {:.0} ms, {} shares",
+            worker.worker, cpu, worker.hashrate, worker.attempts, bailout_ratio, worker.avg_solve_ms, worker.shares
+        );
+        match worker.last_activity.map(|at| now.saturating_sub(at)) {
+            Some(idle) if idle < WORKER_SUMMARY_INTERVAL.as_secs() => println!("{}", line),
+            Some(idle) => println!("{}", format!("{}, no search finished for {} seconds", line, idle).yellow()),
+            None => println!("{}", format!("{}, no search finished yet", line).yellow()),
+        }
+    }
+}
+
+// Solve times against the vdftime budget: many bailouts with solves bunched
+// at the low end suggest a shorter vdftime, solves piling up near 100% a longer one.
+fn print_solve_times(state: &MinerState) {
+    let snapshot = state.solve_budget.snapshot();
+    let buckets: Vec<String> = snapshot
+        .buckets
+        .iter()
+        .enumerate()
+        .map(|(index, count)| match snapshot.bounds.get(index) {
+            Some(bound) => format!("<{:.0}%: {}", bound * 100.0, count),
+            None => format!(">100%: {}", count),
+        })
+        .collect();
+    println!(
+        "{} {} | bailouts: {}",
+        format!("Solve times vs {} ms vdftime:", state.vdftime_ms.load(Ordering::Relaxed)).cyan(),
+        buckets.join("  "),
+        state.bailouts.load(Ordering::Relaxed)
+    );
+}
+
+// One worker per available CPU (or per CPU in --cpus) unless --threads says
+// otherwise. More workers than CPUs is allowed, they just share cores.
+fn worker_count(threads: Option<usize>, placement: &Placement) -> usize {
+    let available = placement.cpu_count();
+    let count = threads.unwrap_or(available);
+    if count > available {
+        println!(
+            "{}",
+            format!("Running {} workers on {} CPUs, some workers will share a core.", count, available).yellow()
+        );
+    }
+    count
+}
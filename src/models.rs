@@ -1,56 +1,310 @@
 use colored::*;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use chrono_tz::Tz;
+use crate::affinity::CpuList;
+use crate::schedule::Schedule;
 
 #[derive(Parser, Debug)]
+#[clap(version, args_conflicts_with_subcommands = true)]
 pub struct Args {
-    #[clap(short, long)]
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    // Mining flags are also accepted without the `mine` subcommand.
+    #[clap(flatten)]
+    pub mine: MineArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Mine against a pool or your own node (the default)
+    Mine(MineArgs),
+    /// Measure solver throughput on random work without connecting anywhere
+    Bench(BenchArgs),
+    /// Check a solution for a block header
+    Verify(VerifyArgs),
+    /// Search a single block header for a solution meeting a target
+    Solve(SolveArgs),
+    /// Serve many local rigs over a single upstream pool connection
+    Proxy(ProxyArgs),
+    /// Inspect the resolved configuration
+    Config {
+        #[clap(subcommand)]
+        action: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print every setting with the file, variable or flag it came from
+    Check(MineArgs),
+}
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct MineArgs {
+    #[clap(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    #[clap(short, long, value_parser = parse_threads)]
     pub threads: Option<usize>,
+    #[clap(long, value_name = "LIST")]
+    pub cpus: Option<CpuList>,
+    #[clap(long, overrides_with = "no_numa")]
+    pub numa: bool,
+    #[clap(long, overrides_with = "numa")]
+    pub no_numa: bool,
+    #[clap(long, overrides_with = "no_background")]
+    pub background: bool,
+    #[clap(long, overrides_with = "background")]
+    pub no_background: bool,
+    #[clap(long, value_name = "PERCENT")]
+    pub max_cpu: Option<Percent>,
+    #[clap(long, value_name = "PERCENT")]
+    pub busy_threshold: Option<Percent>,
+    #[clap(long, value_parser = parse_seconds)]
+    pub calm_period: Option<f64>,
+    #[clap(long, value_name = "WINDOWS")]
+    pub schedule: Option<Schedule>,
+    #[clap(long, value_name = "TZ")]
+    pub timezone: Option<Tz>,
     #[clap(short, long)]
     pub address: Option<String>,
+    #[clap(long, value_name = "PREFIX")]
+    pub address_hrp: Option<String>,
     #[clap(short, long)]
-    pub pool: Option<String>,
+    pub pool: Vec<String>,
     #[clap(short, long)]
-    pub vdftime: Option<String>,
+    pub worker: Option<String>,
+    #[clap(long, value_name = "RPC_URL")]
+    pub solo: Option<String>,
+    #[clap(long, value_parser = parse_seconds)]
+    pub solo_poll: Option<f64>,
+    #[clap(short, long, value_name = "SECONDS|auto")]
+    pub vdftime: Option<VdfTime>,
+    #[clap(long, value_parser = parse_seconds)]
+    pub backoff_initial: Option<f64>,
+    #[clap(long, value_parser = parse_seconds)]
+    pub backoff_max: Option<f64>,
+    #[clap(long)]
+    pub max_retries: Option<u32>,
+    #[clap(long, value_parser = parse_seconds)]
+    pub keepalive: Option<f64>,
+    #[clap(long, value_parser = parse_seconds)]
+    pub idle_timeout: Option<f64>,
+    #[clap(long, value_parser = parse_seconds)]
+    pub job_timeout: Option<f64>,
+    #[clap(long, value_parser = parse_seconds)]
+    pub log_interval: Option<f64>,
+    #[clap(long, value_name = "ADDR:PORT")]
+    pub api_bind: Option<SocketAddr>,
+    #[clap(long, overrides_with = "no_api")]
+    pub api: bool,
+    #[clap(long, overrides_with = "api", conflicts_with_all = ["api_bind", "api_token"])]
+    pub no_api: bool,
+    #[clap(long, value_name = "TOKEN")]
+    pub api_token: Option<String>,
+}
 
-    pub vdftime_parsed: Option<u64>
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    #[clap(short, long, value_parser = parse_threads)]
+    pub threads: Option<usize>,
+    #[clap(short, long, value_parser = parse_seconds)]
+    pub vdftime: Option<f64>,
+    #[clap(short, long, default_value_t = 30.0, value_parser = parse_seconds)]
+    pub duration: f64,
 }
 
-impl Args {
-    pub fn parse_and_validate() -> Args {
-        let mut args = Args::parse();
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    /// Block header before the nonce, as sent in a pool job (76 bytes hex)
+    #[clap(long)]
+    pub data: String,
+    /// Nonce as submitted (4 bytes hex)
+    #[clap(long)]
+    pub nonce: String,
+    /// Hamiltonian cycle as submitted (little-endian u16s, hex)
+    #[clap(long)]
+    pub path: String,
+    /// Also check the resulting hash against this target
+    #[clap(long)]
+    pub target: Option<String>,
+}
 
-        if args.address.is_none() || args.pool.is_none() {
-            Args::show_demo_usage();
-            std::process::exit(0);
-        }
+#[derive(clap::Args, Debug)]
+pub struct SolveArgs {
+    /// Block header before the nonce, as sent in a pool job (76 bytes hex)
+    #[clap(long)]
+    pub data: String,
+    #[clap(long, default_value = "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")]
+    pub target: String,
+    #[clap(short, long, value_parser = parse_seconds)]
+    pub vdftime: Option<f64>,
+    /// Give up after this many nonces
+    #[clap(long)]
+    pub attempts: Option<u64>,
+}
 
-        if let Some(vdftime_str) = args.vdftime.clone() {
-            match vdftime_str.parse::<f64>() {
-                Ok(vdf) => {
-                    args.vdftime_parsed = Some((vdf * 1000.0) as u64);
-                }
-                Err(_) => {
-                    args.vdftime_parsed = None;
-                }
-            }
-        }
+#[derive(clap::Args, Debug, Clone)]
+pub struct ProxyArgs {
+    #[clap(short, long)]
+    pub address: String,
+    #[clap(long, value_name = "PREFIX", default_value = crate::address::MAINNET_HRP)]
+    pub address_hrp: String,
+    #[clap(short, long)]
+    pub pool: String,
+    #[clap(short, long)]
+    pub worker: Option<String>,
+    #[clap(long, default_value = "0.0.0.0:8855")]
+    pub listen: String,
+    #[clap(long, default_value_t = 5.0, value_parser = parse_seconds)]
+    pub backoff_initial: f64,
+    #[clap(long, default_value_t = 300.0, value_parser = parse_seconds)]
+    pub backoff_max: f64,
+    #[clap(long, default_value_t = 30.0, value_parser = parse_seconds)]
+    pub keepalive: f64,
+    #[clap(long, default_value_t = 90.0, value_parser = parse_seconds)]
+    pub idle_timeout: f64,
+    #[clap(long, value_parser = parse_seconds)]
+    pub job_timeout: Option<f64>,
+}
 
-        args
+impl Args {
+    // The mining flags, whether given after `mine` or on their own.
+    pub fn mine_args(&self) -> MineArgs {
+        match &self.command {
+            Some(Command::Mine(mine)) => mine.clone(),
+            _ => self.mine.clone(),
+        }
     }
 
     pub fn show_demo_usage() {
         println!();
         println!("{}", "Run the miner with required arguments:".bold().bright_yellow());
         println!("{}", "--address <shaicoin_address> --pool <POOL_URL>".bold().bright_red());
-        println!("{}", "OPTIONAL: --threads <AMT>".bold().bright_red());
-        println!("{}", "OPTIONAL: --vdftime <SECONDS>".bold().bright_red());
+        println!("{}", "OR solo mine against your own node instead of a pool:".bold().bright_yellow());
+        println!("{}", "--address <shaicoin_address> --solo <RPC_URL>".bold().bright_red());
+        println!("{}", "OPTIONAL: --threads <AMT> --cpus <LIST> --[no-]numa --[no-]background --max-cpu <PERCENT>".bold().bright_red());
+        println!("{}", "OPTIONAL: --busy-threshold <PERCENT> --calm-period <SECONDS>".bold().bright_red());
+        println!("{}", "OPTIONAL: --schedule \"mon-fri 18:00-08:00; sat,sun\" --timezone <TZ>".bold().bright_red());
+        println!("{}", "OPTIONAL: --worker <NAME>".bold().bright_red());
+        println!("{}", "OPTIONAL: --vdftime <SECONDS|auto>".bold().bright_red());
+        println!("{}", "OPTIONAL: --backoff-initial <SECONDS> --backoff-max <SECONDS>".bold().bright_red());
+        println!("{}", "OPTIONAL: --max-retries <AMT>".bold().bright_red());
+        println!("{}", "OPTIONAL: --keepalive <SECONDS> --idle-timeout <SECONDS> --job-timeout <SECONDS>".bold().bright_red());
+        println!("{}", "OPTIONAL: --api-bind <ADDR:PORT> --api-token <TOKEN> --[no-]api".bold().bright_red());
+        println!("{}", "OPTIONAL: --config <FILE> (settings from a TOML file, see `shaipot config check`)".bold().bright_red());
+        println!("{}", "Other commands: bench, verify, solve, proxy, config check (see `shaipot --help`)".bold().bright_yellow());
         println!();
         println!("Example mining with 4 threads:");
         println!("./shaipot --address sh1qeexkz69dz6j4q0zt0pkn36650yevwc8eksqeuu --pool wss://pool.shaicoin.org --threads 4 --vdftime 1.5");
     }
 }
 
+pub fn parse_seconds(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(secs) if secs > 0.0 && secs.is_finite() => Ok(secs),
+        Ok(_) => Err(String::from("must be a positive number of seconds")),
+        Err(e) => Err(format!("not a number of seconds: {}", e)),
+    }
+}
+
+// A share of one CPU's time, written as "50%" or "50".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Percent(pub f64);
+
+impl FromStr for Percent {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let number = value.trim().trim_end_matches('%').trim();
+        match number.parse::<f64>() {
+            Ok(percent) if percent > 0.0 && percent <= 100.0 => Ok(Percent(percent)),
+            Ok(_) => Err(format!("{} must be above 0% and at most 100%", value)),
+            Err(e) => Err(format!("{} is not a percentage: {}", value, e)),
+        }
+    }
+}
+
+impl fmt::Display for Percent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
+// The cycle search bailout: a fixed number of seconds, or "auto" to pick
+// one from the solve times observed while mining.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VdfTime {
+    Seconds(f64),
+    Auto,
+}
+
+impl VdfTime {
+    pub fn from_secs(secs: f64) -> Result<VdfTime, String> {
+        if secs > 0.0 && secs.is_finite() {
+            Ok(VdfTime::Seconds(secs))
+        } else {
+            Err(format!("{} must be a positive number of seconds or auto", secs))
+        }
+    }
+}
+
+impl FromStr for VdfTime {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.trim().eq_ignore_ascii_case("auto") {
+            return Ok(VdfTime::Auto);
+        }
+        match value.trim().parse::<f64>() {
+            Ok(secs) => VdfTime::from_secs(secs),
+            Err(e) => Err(format!("{} is not a number of seconds or auto: {}", value, e)),
+        }
+    }
+}
+
+impl fmt::Display for VdfTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VdfTime::Seconds(secs) => write!(f, "{}", secs),
+            VdfTime::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+pub fn parse_threads(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err(String::from("must be at least 1")),
+        Ok(threads) => Ok(threads),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub const MINER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Protocol extensions this miner understands, advertised to the pool on login.
+pub const SUPPORTED_FEATURES: &[&str] = &["keepalive", "close_codes", "set_target", "set_difficulty", "nonce_range"];
+
+pub fn user_agent() -> String {
+    format!("shaipot/{}", MINER_VERSION)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LoginMessage {
+    pub r#type: String,
+    pub miner_id: String,
+    pub worker: Option<String>,
+    pub agent: String,
+    pub version: String,
+    pub threads: usize,
+    pub features: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SubmitMessage {
     pub r#type: String,
@@ -67,6 +321,14 @@ pub struct ServerMessage {
     pub data: Option<String>,
     pub target: Option<String>,
     pub pplns_score: Option<f64>,
+    pub message: Option<String>,
+    pub balance: Option<f64>,
+    pub amount: Option<f64>,
+    pub txid: Option<String>,
+    pub hashrate: Option<f64>,
+    pub difficulty: Option<f64>,
+    pub nonce_start: Option<u32>,
+    pub nonce_count: Option<u32>,
 }
 
 #[derive(Clone, Debug)]
@@ -74,4 +336,6 @@ pub struct Job {
     pub job_id: String,
     pub data: String,
     pub target: String,
+    // Set when an upstream proxy hands this miner a slice of the nonce space.
+    pub nonce_range: Option<(u32, u32)>,
 }
\ No newline at end of file
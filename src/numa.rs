@@ -0,0 +1,48 @@
+use std::path::Path;
+use crate::affinity::CpuList;
+
+const NODE_ROOT: &str = "/sys/devices/system/node";
+
+#[derive(Clone, Debug)]
+pub struct NumaNode {
+    pub id: usize,
+    pub cpus: CpuList,
+}
+
+// Reads the NUMA topology the kernel exposes under /sys, ordered by node id.
+// Nodes without CPUs (memory-only nodes) are skipped.
+pub fn detect_nodes() -> Result<Vec<NumaNode>, String> {
+    let entries = std::fs::read_dir(NODE_ROOT).map_err(|e| format!("cannot read {}: {}", NODE_ROOT, e))?;
+
+    let mut nodes = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(id) = name.to_str().and_then(|name| name.strip_prefix("node")).and_then(|id| id.parse::<usize>().ok()) else {
+            continue;
+        };
+        let cpulist = Path::new(NODE_ROOT).join(name).join("cpulist");
+        let text = std::fs::read_to_string(&cpulist).map_err(|e| format!("cannot read {}: {}", cpulist.display(), e))?;
+        if text.trim().is_empty() {
+            continue;
+        }
+        let cpus = text.trim().parse::<CpuList>().map_err(|e| format!("{}: {}", cpulist.display(), e))?;
+        nodes.push(NumaNode { id, cpus });
+    }
+
+    if nodes.is_empty() {
+        return Err(format!("no NUMA nodes with CPUs under {}", NODE_ROOT));
+    }
+    nodes.sort_by_key(|node| node.id);
+    Ok(nodes)
+}
+
+// Limits every node to the CPUs in `allowed`, dropping nodes left empty.
+pub fn restrict_nodes(nodes: Vec<NumaNode>, allowed: &CpuList) -> Vec<NumaNode> {
+    nodes
+        .into_iter()
+        .filter_map(|node| {
+            let cpus: Vec<usize> = node.cpus.0.into_iter().filter(|cpu| allowed.0.contains(cpu)).collect();
+            (!cpus.is_empty()).then_some(NumaNode { id: node.id, cpus: CpuList(cpus) })
+        })
+        .collect()
+}
@@ -0,0 +1,403 @@
+use colored::*;
+use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
+use tokio_tungstenite::{accept_async, connect_async};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use crate::address::decode_address;
+use crate::connection::{Backoff, ConnectionStatus, ConnectionTracker, Keepalive, PoolClose};
+use crate::exit_codes;
+use crate::models::{LoginMessage, ProxyArgs, ServerMessage, SUPPORTED_FEATURES, MINER_VERSION};
+
+// The nonce space is split into one slice per rig, keyed by the top byte,
+// so two rigs can never submit the same nonce for the same job.
+const NONCE_SLOTS: usize = 256;
+const NONCE_SLOT_SIZE: u32 = 1 << 24;
+
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Rig {
+    slot: usize,
+    peer: String,
+    worker: std::sync::Mutex<Option<String>>,
+    connected_at: Instant,
+    sender: UnboundedSender<Message>,
+    submitted: AtomicUsize,
+    accepted: AtomicUsize,
+    rejected: AtomicUsize,
+}
+
+impl Rig {
+    fn name(&self) -> String {
+        self.worker.lock().unwrap().clone().unwrap_or_else(|| self.peer.clone())
+    }
+
+    fn nonce_start(&self) -> u32 {
+        (self.slot as u32) * NONCE_SLOT_SIZE
+    }
+
+    fn send(&self, value: &Value) {
+        let _ = self.sender.send(Message::Text(value.to_string()));
+    }
+}
+
+struct ProxyState {
+    rigs: Mutex<HashMap<usize, Arc<Rig>>>,
+    // The last upstream job, kept so rigs keep working through short
+    // upstream outages and new rigs get work immediately.
+    last_job: Mutex<Option<Value>>,
+    // Rigs whose submits are waiting for an upstream verdict, in the order
+    // they were forwarded; the pool answers submits in order.
+    pending: Mutex<VecDeque<usize>>,
+    submits: UnboundedSender<(usize, String)>,
+    // The address the proxy logs in with; every forwarded share is
+    // credited to it, whatever address the rig itself uses.
+    miner_id: String,
+}
+
+impl ProxyState {
+    fn job_for(job: &Value, rig: &Rig) -> Value {
+        let mut job = job.clone();
+        job["nonce_start"] = Value::from(rig.nonce_start());
+        job["nonce_count"] = Value::from(NONCE_SLOT_SIZE);
+        job
+    }
+
+    async fn broadcast(&self, value: &Value) {
+        for rig in self.rigs.lock().await.values() {
+            rig.send(value);
+        }
+    }
+
+    async fn broadcast_job(&self, job: Value) {
+        for rig in self.rigs.lock().await.values() {
+            rig.send(&ProxyState::job_for(&job, rig));
+        }
+        *self.last_job.lock().await = Some(job);
+    }
+
+    async fn verdict(&self, accepted: bool) {
+        let Some(rig_id) = self.pending.lock().await.pop_front() else {
+            return;
+        };
+        let Some(rig) = self.rigs.lock().await.get(&rig_id).cloned() else {
+            return;
+        };
+        if accepted {
+            rig.accepted.fetch_add(1, Ordering::Relaxed);
+            rig.send(&json!({ "type": "accepted" }));
+            println!("{} {}", "Share accepted for".green(), rig.name().bold().cyan());
+        } else {
+            rig.rejected.fetch_add(1, Ordering::Relaxed);
+            rig.send(&json!({ "type": "rejected" }));
+            println!("{} {}", "Share rejected for".red(), rig.name().bold().cyan());
+        }
+    }
+}
+
+pub async fn run_proxy(args: ProxyArgs) {
+    if let Err(e) = decode_address(&args.address, &args.address_hrp.to_lowercase()) {
+        println!("{}", e.bold().red());
+        std::process::exit(exit_codes::BAD_CONFIG);
+    }
+
+    let listener = match TcpListener::bind(&args.listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("{}", format!("Failed to listen on {}: {}", args.listen, e).bold().red());
+            std::process::exit(exit_codes::BIND_FAILED);
+        }
+    };
+    let local_addr = listener.local_addr().map(|addr| addr.to_string()).unwrap_or_else(|_| args.listen.clone());
+    println!("{} {}", "PROXY LISTENING ON".bold().green(), format!("ws://{}", local_addr).bold().cyan());
+
+    let (submits, submit_receiver) = unbounded_channel::<(usize, String)>();
+    let state = Arc::new(ProxyState {
+        rigs: Mutex::new(HashMap::new()),
+        last_job: Mutex::new(None),
+        pending: Mutex::new(VecDeque::new()),
+        submits,
+        miner_id: args.address.clone(),
+    });
+
+    tokio::spawn(run_upstream(args, state.clone(), submit_receiver));
+    tokio::spawn(print_summaries(state.clone()));
+
+    let mut next_rig_id = 0;
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                next_rig_id += 1;
+                tokio::spawn(handle_rig(stream, next_rig_id, state.clone()));
+            }
+            Err(e) => println!("{}", format!("Failed to accept rig: {}", e).red()),
+        }
+    }
+}
+
+// Holds the single pool session. Submits that arrive while the pool is
+// unreachable wait in the channel and go out after the next reconnect.
+async fn run_upstream(args: ProxyArgs, state: Arc<ProxyState>, mut submits: UnboundedReceiver<(usize, String)>) {
+    let backoff = Backoff::new(
+        Duration::from_secs_f64(args.backoff_initial),
+        Duration::from_secs_f64(args.backoff_max),
+    );
+    let status = Arc::new(Mutex::new(ConnectionStatus::new()));
+    let mut connection = ConnectionTracker::new(status, backoff, None);
+    let keepalive_interval = Duration::from_secs_f64(args.keepalive);
+    let idle_timeout = Duration::from_secs_f64(args.idle_timeout);
+    let job_timeout = args.job_timeout.map(Duration::from_secs_f64);
+
+    loop {
+        connection.connecting().await;
+        let request = match args.pool.clone().into_client_request() {
+            Ok(request) => request,
+            Err(e) => {
+                println!("{}", format!("Invalid pool URL {}: {}", args.pool, e).bold().red());
+                std::process::exit(exit_codes::BAD_CONFIG);
+            }
+        };
+
+        let disconnect_reason = match connect_async(request).await {
+            Err(e) => format!("connect failed: {}", e),
+            Ok((ws_stream, _)) => {
+                connection.connected().await;
+                let (mut write, mut read) = ws_stream.split();
+
+                let rigs = state.rigs.lock().await.len();
+                let login = LoginMessage {
+                    r#type: String::from("login"),
+                    miner_id: args.address.clone(),
+                    worker: args.worker.clone(),
+                    agent: format!("shaipot-proxy/{}", MINER_VERSION),
+                    version: String::from(MINER_VERSION),
+                    threads: rigs,
+                    features: SUPPORTED_FEATURES.iter().map(|f| f.to_string()).collect(),
+                };
+                let login = serde_json::to_string(&login).unwrap();
+                let mut reason = match write.send(Message::Text(login)).await {
+                    Ok(_) => None,
+                    Err(e) => Some(format!("login failed: {}", e)),
+                };
+                let mut keepalive = Keepalive::new(idle_timeout, job_timeout);
+                let mut ping_timer = tokio::time::interval_at(tokio::time::Instant::now() + keepalive_interval, keepalive_interval);
+
+                while reason.is_none() {
+                    tokio::select! {
+                        Some((rig_id, submit)) = submits.recv() => {
+                            if let Err(e) = write.send(Message::Text(submit)).await {
+                                reason = Some(format!("submit failed: {}", e));
+                                break;
+                            }
+                            state.pending.lock().await.push_back(rig_id);
+                        }
+                        _ = ping_timer.tick() => {
+                            if let Some(timeout) = keepalive.check() {
+                                println!("{}", format!("Dropping pool connection: {}.", timeout).red());
+                                reason = Some(timeout.to_string());
+                                break;
+                            }
+                            if let Err(e) = write.send(Message::Ping(Vec::new())).await {
+                                reason = Some(format!("ping failed: {}", e));
+                                break;
+                            }
+                            keepalive.ping_sent();
+                        }
+                        msg = read.next() => match msg {
+                            Some(Ok(frame)) => {
+                                keepalive.frame_received();
+                                match frame {
+                                    Message::Text(text) => {
+                                        handle_upstream_message(&state, &mut connection, &mut keepalive, &text).await;
+                                    }
+                                    Message::Pong(_) => {
+                                        keepalive.pong_received();
+                                    }
+                                    Message::Close(frame) => {
+                                        let close = PoolClose::from_frame(frame.as_ref());
+                                        if close.is_fatal() {
+                                            connection.failed(close.to_string()).await;
+                                            println!("{}", format!("{}. Reconnecting will not help, exiting.", close).bold().red());
+                                            std::process::exit(close.exit_code.unwrap());
+                                        }
+                                        reason = Some(close.to_string());
+                                    }
+                                    _ => {}
+                                }
+                            }
+                            Some(Err(e)) => reason = Some(format!("read failed: {}", e)),
+                            None => reason = Some(String::from("stream ended")),
+                        },
+                    }
+                }
+
+                // Verdicts for these will never arrive on a new session.
+                let lost = std::mem::take(&mut *state.pending.lock().await);
+                if !lost.is_empty() {
+                    println!("{}", format!("{} forwarded shares lost with the pool connection", lost.len()).red());
+                }
+                reason.unwrap_or_default()
+            }
+        };
+
+        let delay = connection.backoff(disconnect_reason).await.unwrap_or(Duration::from_secs(5));
+        println!(
+            "{}",
+            format!("Upstream unavailable, serving the last job. Reconnecting in {:.1} seconds...", delay.as_secs_f64()).yellow()
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn handle_upstream_message(state: &ProxyState, connection: &mut ConnectionTracker, keepalive: &mut Keepalive, text: &str) {
+    let Ok(value) = serde_json::from_str::<Value>(text) else {
+        println!("{}", "Ignoring malformed message from pool".red());
+        return;
+    };
+    let Ok(server_message) = serde_json::from_value::<ServerMessage>(value.clone()) else {
+        println!("{}", "Ignoring malformed message from pool".red());
+        return;
+    };
+
+    match server_message.r#type.as_str() {
+        "job" => {
+            keepalive.job_received();
+            connection.subscribed().await;
+            println!(
+                "{} {}",
+                "Received new job:".bold().blue(),
+                format!("ID = {}", server_message.job_id.unwrap_or_default()).bold().yellow()
+            );
+            state.broadcast_job(value).await;
+        }
+        "accepted" => state.verdict(true).await,
+        "rejected" => state.verdict(false).await,
+        "set_target" | "set_difficulty" | "notice" | "error" => state.broadcast(&value).await,
+        "login_accepted" => println!("{}", "Logged in to pool".bold().green()),
+        "login_rejected" => {
+            let message = server_message.message.unwrap_or_else(|| String::from("no reason given"));
+            connection.failed(format!("login rejected: {}", message)).await;
+            println!("{}", format!("Pool rejected login: {}. Reconnecting will not help, exiting.", message).bold().red());
+            std::process::exit(exit_codes::POOL_LOGIN_REJECTED);
+        }
+        _ => {}
+    }
+}
+
+async fn handle_rig(stream: TcpStream, rig_id: usize, state: Arc<ProxyState>) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    let ws_stream = match accept_async(stream).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            println!("{}", format!("Handshake with {} failed: {}", peer, e).red());
+            return;
+        }
+    };
+    let (mut write, mut read) = ws_stream.split();
+
+    let (sender, mut outgoing) = unbounded_channel::<Message>();
+    let rig = {
+        let mut rigs = state.rigs.lock().await;
+        let taken: Vec<usize> = rigs.values().map(|rig| rig.slot).collect();
+        let Some(slot) = (0..NONCE_SLOTS).find(|slot| !taken.contains(slot)) else {
+            drop(rigs);
+            let frame = CloseFrame {
+                code: CloseCode::Again,
+                reason: "proxy is full".into(),
+            };
+            let _ = write.send(Message::Close(Some(frame))).await;
+            return;
+        };
+        let rig = Arc::new(Rig {
+            slot,
+            peer: peer.clone(),
+            worker: std::sync::Mutex::new(None),
+            connected_at: Instant::now(),
+            sender,
+            submitted: AtomicUsize::new(0),
+            accepted: AtomicUsize::new(0),
+            rejected: AtomicUsize::new(0),
+        });
+        rigs.insert(rig_id, rig.clone());
+        rig
+    };
+    println!("{} {}", "Rig connected:".bold().green(), peer.bold().cyan());
+
+    if let Some(job) = state.last_job.lock().await.as_ref() {
+        rig.send(&ProxyState::job_for(job, &rig));
+    }
+
+    loop {
+        tokio::select! {
+            Some(msg) = outgoing.recv() => {
+                if write.send(msg).await.is_err() {
+                    break;
+                }
+            }
+            msg = read.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                let Ok(mut value) = serde_json::from_str::<Value>(&text) else {
+                    continue;
+                };
+                match value.get("type").and_then(Value::as_str) {
+                    Some("login") => {
+                        if let Some(worker) = value.get("worker").and_then(Value::as_str) {
+                            *rig.worker.lock().unwrap() = Some(worker.to_string());
+                        }
+                        rig.send(&json!({ "type": "login_accepted" }));
+                    }
+                    Some("submit") => {
+                        let nonce = value
+                            .get("nonce")
+                            .and_then(Value::as_str)
+                            .and_then(|n| u32::from_str_radix(n, 16).ok());
+                        let in_range = nonce.is_some_and(|n| n.wrapping_sub(rig.nonce_start()) < NONCE_SLOT_SIZE);
+                        if !in_range {
+                            rig.rejected.fetch_add(1, Ordering::Relaxed);
+                            rig.send(&json!({ "type": "rejected", "message": "nonce outside assigned range" }));
+                            continue;
+                        }
+                        rig.submitted.fetch_add(1, Ordering::Relaxed);
+                        value["miner_id"] = Value::from(state.miner_id.clone());
+                        let _ = state.submits.send((rig_id, value.to_string()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    state.rigs.lock().await.remove(&rig_id);
+    println!("{} {}", "Rig disconnected:".bold().yellow(), rig.name().bold().cyan());
+}
+
+async fn print_summaries(state: Arc<ProxyState>) {
+    loop {
+        tokio::time::sleep(SUMMARY_INTERVAL).await;
+        let rigs = state.rigs.lock().await;
+        println!("{} {}", "Connected rigs:".bold().cyan(), rigs.len());
+        for rig in rigs.values() {
+            println!(
+                "  {} submitted {} accepted {} rejected {} up {}s",
+                rig.name().bold(),
+                rig.submitted.load(Ordering::Relaxed),
+                rig.accepted.load(Ordering::Relaxed).to_string().green(),
+                rig.rejected.load(Ordering::Relaxed).to_string().red(),
+                rig.connected_at.elapsed().as_secs()
+            );
+        }
+    }
+}
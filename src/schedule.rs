@@ -0,0 +1,298 @@
+// Mining windows such as "mon-fri 18:00-08:00; sat,sun" evaluated in a
+// configurable time zone. Outside every window the workers idle while the
+// pool connection stays up.
+use chrono::{DateTime, Datelike, Duration, Local, Timelike, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const MINUTES_PER_DAY: u32 = 24 * 60;
+// Far enough ahead to see every window of a weekly schedule at least once.
+const LOOKAHEAD_MINUTES: i64 = 8 * MINUTES_PER_DAY as i64;
+
+// One window: a set of weekdays and a time range starting on those days.
+// A range that ends before it starts runs past midnight into the next day.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Window {
+    days: [bool; 7],
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    fn contains(&self, weekday: usize, minute: u32) -> bool {
+        let yesterday = (weekday + 6) % 7;
+        if self.start < self.end {
+            self.days[weekday] && minute >= self.start && minute < self.end
+        } else {
+            (self.days[weekday] && minute >= self.start) || (self.days[yesterday] && minute < self.end)
+        }
+    }
+}
+
+fn parse_day(name: &str) -> Result<usize, String> {
+    DAY_NAMES
+        .iter()
+        .position(|day| name.to_lowercase().starts_with(day))
+        .ok_or_else(|| format!("unknown day '{}'", name))
+}
+
+fn parse_days(value: &str) -> Result<[bool; 7], String> {
+    let mut days = [false; 7];
+    if value == "*" || value.eq_ignore_ascii_case("daily") {
+        return Ok([true; 7]);
+    }
+    for part in value.split(',').map(str::trim) {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse_day(first)?, parse_day(last)?);
+                let mut day = first;
+                loop {
+                    days[day] = true;
+                    if day == last {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            }
+            None => days[parse_day(part)?] = true,
+        }
+    }
+    Ok(days)
+}
+
+// Minutes past midnight. 24:00 is only allowed as the end of a range.
+fn parse_time(value: &str, is_end: bool) -> Result<u32, String> {
+    let invalid = || format!("invalid time '{}', expected HH:MM", value);
+    let (hours, minutes) = value.split_once(':').ok_or_else(invalid)?;
+    let (hours, minutes) = (hours.parse::<u32>().map_err(|_| invalid())?, minutes.parse::<u32>().map_err(|_| invalid())?);
+    let minute = hours * 60 + minutes;
+    if minutes >= 60 || minute > MINUTES_PER_DAY {
+        return Err(invalid());
+    }
+    if minute == MINUTES_PER_DAY && !is_end {
+        return Err(format!("invalid start time '{}', a window can end at 24:00 but not start there", value));
+    }
+    Ok(minute)
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let (days, times) = match value.split_once(char::is_whitespace) {
+            Some((days, times)) => (days, Some(times.trim())),
+            None if value.contains(':') => ("*", Some(value)),
+            None => (value, None),
+        };
+        let days = parse_days(days)?;
+        let (start, end) = match times {
+            Some(times) => {
+                let (start, end) = times.split_once('-').ok_or_else(|| format!("invalid time range '{}', expected HH:MM-HH:MM", times))?;
+                (parse_time(start.trim(), false)?, parse_time(end.trim(), true)?)
+            }
+            None => (0, MINUTES_PER_DAY),
+        };
+        if start == end {
+            return Err(format!("the window '{}' is empty", value));
+        }
+        Ok(Window { days, start, end })
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for day in (0..7).filter(|day| self.days[*day]) {
+            match runs.last_mut() {
+                Some((_, last)) if *last + 1 == day => *last = day,
+                _ => runs.push((day, day)),
+            }
+        }
+        let days = match runs.as_slice() {
+            [(0, 6)] => String::from("*"),
+            runs => runs
+                .iter()
+                .map(|&(first, last)| match last - first {
+                    0 => DAY_NAMES[first].to_string(),
+                    1 => format!("{},{}", DAY_NAMES[first], DAY_NAMES[last]),
+                    _ => format!("{}-{}", DAY_NAMES[first], DAY_NAMES[last]),
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+        };
+        let time = |minute: u32| format!("{:02}:{:02}", minute / 60, minute % 60);
+        write!(f, "{} {}-{}", days, time(self.start), time(self.end))
+    }
+}
+
+// Windows separated by ';', mining whenever any of them is open.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule(pub Vec<Window>);
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let windows = value
+            .split(';')
+            .map(str::trim)
+            .filter(|window| !window.is_empty())
+            .map(str::parse::<Window>)
+            .collect::<Result<Vec<_>, _>>()?;
+        if windows.is_empty() {
+            return Err(String::from("the schedule has no windows"));
+        }
+        Ok(Schedule(windows))
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let windows: Vec<String> = self.0.iter().map(Window::to_string).collect();
+        write!(f, "{}", windows.join("; "))
+    }
+}
+
+// A schedule bound to the time zone its windows are written in; the
+// machine's local time when no zone is configured.
+pub struct MiningSchedule {
+    schedule: Schedule,
+    timezone: Option<Tz>,
+}
+
+impl MiningSchedule {
+    pub fn new(schedule: Schedule, timezone: Option<Tz>) -> MiningSchedule {
+        MiningSchedule { schedule, timezone }
+    }
+
+    pub fn is_open(&self, at: DateTime<Utc>) -> bool {
+        let (weekday, minute) = match self.timezone {
+            Some(tz) => {
+                let local = at.with_timezone(&tz);
+                (local.weekday().num_days_from_monday(), local.hour() * 60 + local.minute())
+            }
+            None => {
+                let local = at.with_timezone(&Local);
+                (local.weekday().num_days_from_monday(), local.hour() * 60 + local.minute())
+            }
+        };
+        self.schedule.0.iter().any(|window| window.contains(weekday as usize, minute))
+    }
+
+    // The first minute after `at` where the schedule opens or closes, or
+    // None when it is always open. Stepping through real instants rather
+    // than wall-clock minutes keeps daylight saving changes right.
+    pub fn next_transition(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let open = self.is_open(at);
+        let first = at.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        (0..LOOKAHEAD_MINUTES)
+            .map(|step| first + Duration::minutes(step))
+            .find(|minute| self.is_open(*minute) != open)
+    }
+
+    // `at` in the schedule's time zone, for display.
+    pub fn format(&self, at: DateTime<Utc>) -> String {
+        match self.timezone {
+            Some(tz) => at.with_timezone(&tz).format("%Y-%m-%d %H:%M %Z").to_string(),
+            None => at.with_timezone(&Local).format("%Y-%m-%d %H:%M %Z").to_string(),
+        }
+    }
+
+    pub fn to_rfc3339(&self, at: DateTime<Utc>) -> String {
+        match self.timezone {
+            Some(tz) => at.with_timezone(&tz).to_rfc3339(),
+            None => at.with_timezone(&Local).to_rfc3339(),
+        }
+    }
+}
+
+impl fmt::Display for MiningSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.timezone {
+            Some(tz) => write!(f, "{} ({})", self.schedule, tz),
+            None => write!(f, "{} (local time)", self.schedule),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn utc(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_windows() {
+        let schedule: Schedule = "mon-fri 18:00-08:00; sat,sun".parse().unwrap();
+        assert_eq!(
+            schedule.0,
+            vec![
+                Window { days: [true, true, true, true, true, false, false], start: 18 * 60, end: 8 * 60 },
+                Window { days: [false, false, false, false, false, true, true], start: 0, end: MINUTES_PER_DAY },
+            ]
+        );
+        assert_eq!(schedule.to_string(), "mon-fri 18:00-08:00; sat,sun 00:00-24:00");
+    }
+
+    #[test]
+    fn contains_across_midnight() {
+        let weeknights: Window = "mon-fri 18:00-08:00".parse().unwrap();
+        assert!(weeknights.contains(0, 19 * 60));
+        assert!(weeknights.contains(1, 7 * 60));
+        assert!(!weeknights.contains(1, 8 * 60));
+        assert!(!weeknights.contains(1, 12 * 60));
+        // Monday morning belongs to Sunday evening, which is not listed.
+        assert!(!weeknights.contains(0, 7 * 60));
+        // Saturday morning still belongs to Friday evening.
+        assert!(weeknights.contains(5, 7 * 60));
+        assert!(!weeknights.contains(5, 19 * 60));
+
+        let sunday_night: Window = "sun 22:00-02:00".parse().unwrap();
+        assert!(sunday_night.contains(6, 23 * 60));
+        assert!(sunday_night.contains(0, 60));
+        assert!(!sunday_night.contains(0, 23 * 60));
+    }
+
+    #[test]
+    fn midnight_at_the_end_only() {
+        assert!("24:00-08:00".parse::<Window>().is_err());
+        let evening: Window = "18:00-24:00".parse().unwrap();
+        assert_eq!((evening.start, evening.end), (18 * 60, MINUTES_PER_DAY));
+        assert!(evening.contains(3, 23 * 60 + 59));
+        assert!(!evening.contains(4, 0));
+        assert!("mon 24:01-08:00".parse::<Window>().is_err());
+    }
+
+    #[test]
+    fn empty_windows_rejected() {
+        assert!("mon 08:00-08:00".parse::<Window>().is_err());
+        assert!("".parse::<Schedule>().is_err());
+        assert!(" ; ".parse::<Schedule>().is_err());
+    }
+
+    // Berlin skips 02:00-03:00 on 2024-03-31 and repeats 02:00-03:00 on
+    // 2024-10-27; transitions must land on the real instants.
+    #[test]
+    fn next_transition_across_dst() {
+        let spring = MiningSchedule::new("03:00-04:00".parse().unwrap(), Some(chrono_tz::Europe::Berlin));
+        let before = utc(2024, 3, 31, 0, 30);
+        assert!(!spring.is_open(before));
+        let opens = spring.next_transition(before).unwrap();
+        assert_eq!(opens, utc(2024, 3, 31, 1, 0));
+        assert_eq!(spring.next_transition(opens).unwrap(), utc(2024, 3, 31, 2, 0));
+
+        let autumn = MiningSchedule::new("00:00-02:30".parse().unwrap(), Some(chrono_tz::Europe::Berlin));
+        let start = utc(2024, 10, 27, 0, 0);
+        assert!(autumn.is_open(start));
+        let closes = autumn.next_transition(start).unwrap();
+        assert_eq!(closes, utc(2024, 10, 27, 0, 30));
+        // The clock falls back to 02:00, inside the window again.
+        assert_eq!(autumn.next_transition(closes).unwrap(), utc(2024, 10, 27, 1, 0));
+    }
+}
@@ -0,0 +1,300 @@
+use colored::*;
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::UnboundedReceiver;
+use crate::api::MinerState;
+use crate::ascii_art::display_share_accepted;
+use crate::block::{build_coinbase, header_prefix, serialize_block, template_merkle_root, BlockTemplate};
+use crate::connection::ConnectionTracker;
+use crate::exit_codes;
+use crate::models::{Job, SubmitMessage};
+use crate::rpc::{RpcClient, RpcError};
+use crate::utils::target_to_difficulty;
+
+// Templates are republished at least this often so new mempool
+// transactions and a fresh timestamp make it into the work.
+const TEMPLATE_REFRESH: Duration = Duration::from_secs(30);
+
+struct SoloJob {
+    template: BlockTemplate,
+    header_prefix: String,
+    coinbase: String,
+}
+
+async fn get_block_template(rpc: &RpcClient, longpollid: Option<&str>) -> Result<BlockTemplate, RpcError> {
+    let mut request = json!({ "rules": ["segwit"] });
+    if let Some(longpollid) = longpollid {
+        request["longpollid"] = Value::from(longpollid);
+    }
+    let result = rpc.call("getblocktemplate", json!([request])).await?;
+    serde_json::from_value(result).map_err(|e| RpcError::Malformed(format!("getblocktemplate: {}", e)))
+}
+
+fn build_job(job_id: String, template: BlockTemplate, payout_script: &[u8]) -> Result<(Job, SoloJob), String> {
+    // Always pay --address. A `coinbasetxn` offered by the node pays
+    // whoever the node chose, so it is ignored.
+    let coinbase = build_coinbase(&template, payout_script)?;
+
+    let merkle_root = template_merkle_root(&template, coinbase.txid)?;
+    let header_prefix = header_prefix(&template, &merkle_root)?;
+    let coinbase = coinbase.data;
+
+    let job = Job {
+        job_id,
+        data: header_prefix.clone(),
+        target: template.target.clone(),
+        nonce_range: None,
+    };
+    Ok((job, SoloJob { template, header_prefix, coinbase }))
+}
+
+// Keeps fetching templates, using long polling when the node offers it,
+// and owns the connection state for the node.
+async fn fetch_templates(
+    rpc: Arc<RpcClient>,
+    templates: tokio::sync::mpsc::Sender<BlockTemplate>,
+    current_job: Arc<Mutex<Option<Job>>>,
+    mut connection: ConnectionTracker,
+    poll_interval: Duration,
+) {
+    let mut longpollid: Option<String> = None;
+    connection.connecting().await;
+
+    loop {
+        let started = Instant::now();
+        match get_block_template(&rpc, longpollid.as_deref()).await {
+            Ok(template) => {
+                connection.subscribed().await;
+                // A node that answers a long poll straight away with the same
+                // id does not really support it, so fall back to polling.
+                let unchanged = template.longpollid.is_some() && template.longpollid == longpollid;
+                longpollid = template.longpollid.clone();
+                if templates.send(template).await.is_err() {
+                    return;
+                }
+                if longpollid.is_none() || (unchanged && started.elapsed() < Duration::from_secs(1)) {
+                    tokio::time::sleep(poll_interval).await;
+                }
+            }
+            Err(e) => {
+                longpollid = None;
+                *current_job.lock().await = None;
+                let Some(delay) = connection.backoff(format!("getblocktemplate failed: {}", e)).await else {
+                    println!("{}", "Giving up after reaching --max-retries.".bold().red());
+                    std::process::exit(exit_codes::CONNECTION_FAILED);
+                };
+                println!("{}", format!("Node unavailable will retry in {:.1} seconds...", delay.as_secs_f64()).red());
+                tokio::time::sleep(delay).await;
+                connection.connecting().await;
+            }
+        }
+    }
+}
+
+pub async fn run_solo(
+    rpc: RpcClient,
+    payout_script: Vec<u8>,
+    current_job: Arc<Mutex<Option<Job>>>,
+    mut submissions: UnboundedReceiver<String>,
+    miner_state: Arc<MinerState>,
+    connection: ConnectionTracker,
+    poll_interval: Duration,
+) {
+    println!("{} {}", "Solo mining against node".bold().green(), rpc.endpoint().bold().cyan());
+    let rpc = Arc::new(rpc);
+
+    let (template_sender, mut template_receiver) = tokio::sync::mpsc::channel::<BlockTemplate>(4);
+    tokio::spawn(fetch_templates(rpc.clone(), template_sender, current_job.clone(), connection, poll_interval));
+
+    let mut jobs: HashMap<String, SoloJob> = HashMap::new();
+    let mut next_job_id: u64 = 0;
+    let mut published: Option<(String, Option<String>, Instant)> = None;
+
+    loop {
+        let template = tokio::select! {
+            Some(template) = template_receiver.recv() => template,
+            Some(msg) = submissions.recv() => {
+                submit_block(&rpc, &jobs, &miner_state, &msg).await;
+                // The worker dropped the job after finding a solution, so
+                // move on to a fresh template straight away.
+                match get_block_template(&rpc, None).await {
+                    Ok(template) => {
+                        published = None;
+                        template
+                    }
+                    Err(_) => continue,
+                }
+            }
+        };
+
+        let job_missing = current_job.lock().await.is_none();
+        let changed = match &published {
+            Some((prevhash, longpollid, at)) => {
+                *prevhash != template.previousblockhash
+                    || (template.longpollid.is_some() && *longpollid != template.longpollid)
+                    || at.elapsed() >= TEMPLATE_REFRESH
+                    || job_missing
+            }
+            None => true,
+        };
+        if !changed {
+            continue;
+        }
+
+        let new_block = published
+            .as_ref()
+            .is_none_or(|(prevhash, _, _)| *prevhash != template.previousblockhash);
+        if new_block {
+            jobs.clear();
+        }
+
+        next_job_id += 1;
+        let job_id = format!("{:x}", next_job_id);
+        let (height, tx_count, prevhash, longpollid) = (
+            template.height,
+            template.transactions.len(),
+            template.previousblockhash.clone(),
+            template.longpollid.clone(),
+        );
+        match build_job(job_id.clone(), template, &payout_script) {
+            Ok((job, solo_job)) => {
+                *miner_state.difficulty.lock().await = target_to_difficulty(&job.target);
+                println!(
+                    "{} {}",
+                    "Received new block template:".bold().blue(),
+                    format!("Height = {}, Transactions = {}, Target = {}", height, tx_count, job.target)
+                        .bold()
+                        .yellow()
+                );
+                jobs.insert(job_id, solo_job);
+                *current_job.lock().await = Some(job);
+                published = Some((prevhash, longpollid, Instant::now()));
+            }
+            Err(e) => {
+                println!("{}", format!("Cannot mine block template: {}", e).red());
+                *current_job.lock().await = None;
+                published = None;
+            }
+        }
+    }
+}
+
+async fn submit_block(rpc: &RpcClient, jobs: &HashMap<String, SoloJob>, miner_state: &MinerState, msg: &str) {
+    let Ok(submit) = serde_json::from_str::<SubmitMessage>(msg) else {
+        return;
+    };
+    let pending = miner_state.pending_shares.lock().await.pop_front();
+    let difficulty = pending.as_ref().map(|pending| pending.difficulty);
+    let Some(job) = jobs.get(&submit.job_id) else {
+        miner_state.rejected_shares.fetch_add(1, Ordering::Relaxed);
+        println!("{}", "Solution is for a stale block template, dropping it.".red());
+        return;
+    };
+
+    let block = serialize_block(&job.header_prefix, &submit.nonce, &submit.path, &job.coinbase, &job.template.transactions);
+    let result = rpc.call("submitblock", json!([block])).await;
+    if let Some(pending) = &pending {
+        miner_state.submit_latency.observe(pending.submitted.elapsed());
+    }
+    match result {
+        Ok(Value::Null) => {
+            miner_state.accepted_shares.fetch_add(1, Ordering::Relaxed);
+            *miner_state.accepted_work.lock().await += difficulty.unwrap_or_else(|| target_to_difficulty(&job.template.target));
+            println!("{}", format!("Block {} accepted by node!", job.template.height).bold().green());
+            display_share_accepted();
+        }
+        Ok(reason) => {
+            miner_state.rejected_shares.fetch_add(1, Ordering::Relaxed);
+            println!("{}", format!("Block rejected by node: {}", reason).red());
+        }
+        Err(e) => {
+            miner_state.rejected_shares.fetch_add(1, Ordering::Relaxed);
+            println!("{}", format!("Failed to submit block: {}", e).red());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::sha256d;
+    use crate::hasher::{compute_hash_no_vdf, verify_solution};
+    use crate::vdf_solution::HCGraphUtil;
+    use sha2::{Digest, Sha256};
+
+    const PAYOUT_SCRIPT: &str = "0014ce4d6168ad16a5503c4b786d38eb547932c760f9";
+
+    // A node coinbase paying someone else must not end up in the block.
+    #[test]
+    fn node_coinbase_is_ignored() {
+        let node_coinbase = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0100ffffffff0100f2052a010000001600140000000000000000000000000000000000000000000000000000";
+        let template: BlockTemplate = serde_json::from_value(json!({
+            "version": 0x20000000,
+            "previousblockhash": "11".repeat(32),
+            "transactions": [],
+            "coinbasevalue": 5_000_000_000u64,
+            "target": "f".repeat(64),
+            "curtime": 1_700_000_000,
+            "bits": "207fffff",
+            "height": 100,
+            "coinbasetxn": { "data": node_coinbase },
+        }))
+        .unwrap();
+        let payout_script = hex::decode(PAYOUT_SCRIPT).unwrap();
+
+        let (job, solo_job) = build_job(String::from("1"), template.clone(), &payout_script).unwrap();
+        let expected = build_coinbase(&template, &payout_script).unwrap();
+        assert_eq!(solo_job.coinbase, expected.data);
+        assert_ne!(solo_job.coinbase, node_coinbase);
+        assert!(solo_job.coinbase.contains(PAYOUT_SCRIPT));
+        let merkle_root = template_merkle_root(&template, expected.txid).unwrap();
+        assert_eq!(job.data, header_prefix(&template, &merkle_root).unwrap());
+    }
+
+    // The header a block template turns into, laid out by hand: every field
+    // little-endian, the previous hash in internal (reversed) byte order,
+    // then the nonce and the vdf path. The hash the hasher reports for a
+    // solved job must be the reversed SHA-256 of exactly those bytes.
+    #[test]
+    fn solved_header_layout() {
+        let previousblockhash: String = (0u8..32).map(|b| format!("{:02x}", b)).collect();
+        let template: BlockTemplate = serde_json::from_value(json!({
+            "version": 0x20000000,
+            "previousblockhash": previousblockhash,
+            "transactions": [],
+            "coinbasevalue": 5_000_000_000u64,
+            "target": "f".repeat(64),
+            "curtime": 0x65432100,
+            "bits": "1d00ffff",
+            "height": 1000,
+        }))
+        .unwrap();
+        let payout_script = hex::decode(PAYOUT_SCRIPT).unwrap();
+        let (job, solo_job) = build_job(String::from("1"), template, &payout_script).unwrap();
+
+        let previous_internal: String = (0u8..32).rev().map(|b| format!("{:02x}", b)).collect();
+        let merkle_root = hex::encode(sha256d(&hex::decode(&solo_job.coinbase).unwrap()));
+        let expected_prefix = format!("00000020{}{}00214365ffff001d", previous_internal, merkle_root);
+        assert_eq!(job.data, expected_prefix);
+
+        let mut hc_util = HCGraphUtil::new(Some(30_000));
+        let (nonce, hash, path) = (0u32..)
+            .map(|n| hex::encode(n.to_le_bytes()))
+            .find_map(|nonce| compute_hash_no_vdf(&format!("{}{}", job.data, nonce), &mut hc_util).map(|(hash, path)| (nonce, hash, path)))
+            .unwrap();
+        let verified = verify_solution(&format!("{}{}", job.data, nonce), &path, &HCGraphUtil::new(None)).unwrap();
+        assert_eq!(verified, hash);
+
+        let header = hex::decode(format!("{}{}{}", expected_prefix, nonce, path)).unwrap();
+        let mut expected_hash = Sha256::digest(&header).to_vec();
+        expected_hash.reverse();
+        assert_eq!(verified, hex::encode(expected_hash));
+
+        let block = serialize_block(&job.data, &nonce, &path, &solo_job.coinbase, &[]);
+        assert!(block.starts_with(&hex::encode(&header)));
+    }
+}
@@ -0,0 +1,138 @@
+// Offline commands: `bench`, `verify` and `solve` exercise the solver
+// directly without a pool or node.
+use colored::*;
+use rand::Rng;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::exit_codes;
+use crate::hasher::{compute_hash_no_vdf, verify_solution};
+use crate::models::{BenchArgs, SolveArgs, VerifyArgs};
+use crate::utils::{generate_nonce, meets_target};
+use crate::vdf_solution::{HCGraphUtil, GRAPH_SIZE};
+
+const HEADER_PREFIX_BYTES: usize = 76;
+const NONCE_BYTES: usize = 4;
+const TARGET_BYTES: usize = 32;
+
+fn require_hex(value: &str, bytes: usize, flag: &str) {
+    match hex::decode(value) {
+        Ok(decoded) if decoded.len() == bytes => {}
+        _ => {
+            println!("{}", format!("{} must be {} bytes of hex", flag, bytes).bold().red());
+            std::process::exit(exit_codes::BAD_CONFIG);
+        }
+    }
+}
+
+fn bailout_ms(vdftime: Option<f64>) -> Option<u64> {
+    vdftime.map(|secs| (secs * 1000.0) as u64)
+}
+
+pub fn run_bench(args: &BenchArgs) {
+    let threads = args.threads.unwrap_or_else(num_cpus::get);
+    let bailout_timer = bailout_ms(args.vdftime);
+    println!(
+        "{} {}",
+        "Benchmarking".bold().green(),
+        format!(
+            "{} threads for {} seconds with a {} second vdftime",
+            threads,
+            args.duration,
+            args.vdftime.unwrap_or(1.0)
+        )
+        .bold()
+        .cyan()
+    );
+
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let solved = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs_f64(args.duration);
+
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let attempts = attempts.clone();
+            let solved = solved.clone();
+            thread::spawn(move || {
+                let mut hc_util = HCGraphUtil::new(bailout_timer);
+                let header: [u8; HEADER_PREFIX_BYTES] = std::array::from_fn(|_| rand::thread_rng().gen());
+                let header = hex::encode(header);
+                while Instant::now() < deadline {
+                    let data = format!("{}{}", header, generate_nonce());
+                    attempts.fetch_add(1, Ordering::Relaxed);
+                    if compute_hash_no_vdf(&data, &mut hc_util).is_some() {
+                        solved.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    let elapsed = started.elapsed().as_secs_f64();
+    let attempts = attempts.load(Ordering::Relaxed);
+    let solved = solved.load(Ordering::Relaxed);
+    let bailout_ratio = if attempts == 0 { 0.0 } else { (attempts - solved) as f64 / attempts as f64 };
+    println!("{}: {:.2}/second ({} total)", "Graph attempts".cyan(), attempts as f64 / elapsed, attempts);
+    println!("{}: {:.2}/second ({} total)", "Solved cycles".cyan(), solved as f64 / elapsed, solved);
+    println!("{}: {:.1}%", "Bailouts".cyan(), bailout_ratio * 100.0);
+}
+
+pub fn run_verify(args: &VerifyArgs) {
+    require_hex(&args.data, HEADER_PREFIX_BYTES, "--data");
+    require_hex(&args.nonce, NONCE_BYTES, "--nonce");
+    require_hex(&args.path, usize::from(GRAPH_SIZE) * 2, "--path");
+    if let Some(target) = &args.target {
+        require_hex(target, TARGET_BYTES, "--target");
+    }
+
+    let hc_util = HCGraphUtil::new(None);
+    let Some(hash) = verify_solution(&format!("{}{}", args.data, args.nonce), &args.path, &hc_util) else {
+        println!("{}", "Invalid solution: the path is not a Hamiltonian cycle of this header's graph.".bold().red());
+        std::process::exit(1);
+    };
+    println!("{} {}", "Valid cycle, hash:".bold().green(), hash);
+
+    if let Some(target) = &args.target {
+        if !meets_target(&hash, target) {
+            println!("{}", "The hash does not meet the target.".bold().red());
+            std::process::exit(1);
+        }
+        println!("{}", "The hash meets the target.".bold().green());
+    }
+}
+
+pub fn run_solve(args: &SolveArgs) {
+    require_hex(&args.data, HEADER_PREFIX_BYTES, "--data");
+    require_hex(&args.target, TARGET_BYTES, "--target");
+
+    let mut hc_util = HCGraphUtil::new(bailout_ms(args.vdftime));
+    let started = Instant::now();
+    let mut attempts: u64 = 0;
+    loop {
+        if args.attempts.is_some_and(|max| attempts >= max) {
+            println!("{}", format!("No solution found in {} attempts.", attempts).bold().red());
+            std::process::exit(1);
+        }
+        attempts += 1;
+
+        let nonce = generate_nonce();
+        let Some((hash, path)) = compute_hash_no_vdf(&format!("{}{}", args.data, nonce), &mut hc_util) else {
+            continue;
+        };
+        if meets_target(&hash, &args.target) {
+            println!(
+                "{}",
+                format!("Solved after {} attempts in {:.1} seconds", attempts, started.elapsed().as_secs_f64()).bold().green()
+            );
+            println!("nonce: {}", nonce);
+            println!("hash: {}", hash);
+            println!("path: {}", path);
+            return;
+        }
+    }
+}
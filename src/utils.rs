@@ -1,5 +1,6 @@
 use rand::Rng;
 use tokio::signal;
+use tokio::sync::mpsc::UnboundedSender;
 use std::process::exit;
 use primitive_types::U256;
 use super::ascii_art::print_exit_art;
@@ -26,14 +27,74 @@ pub async fn handle_exit_signals() {
     exit(0);
 }
 
+// Forwards every SIGHUP so the miner can reload its configuration.
+pub async fn handle_reload_signal(reload: UnboundedSender<()>) {
+    #[cfg(unix)]
+    {
+        let mut sighup = signal(SignalKind::hangup()).expect("Failed to create SIGHUP handler");
+        while sighup.recv().await.is_some() {
+            if reload.send(()).is_err() {
+                break;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = reload;
+        std::future::pending::<()>().await;
+    }
+}
+
 pub fn generate_nonce() -> String {
     let mut rng = rand::thread_rng();
     let nonce: u32 = rng.gen();
     format!("{:08x}", nonce)
 }
 
+// Picks a nonce from `count` values starting at `start`, wrapping at u32::MAX.
+pub fn generate_nonce_in(start: u32, count: u32) -> String {
+    if count == 0 {
+        return generate_nonce();
+    }
+    let offset: u32 = rand::thread_rng().gen_range(0..count);
+    format!("{:08x}", start.wrapping_add(offset))
+}
+
 pub fn meets_target(hash: &str, target: &str) -> bool {
     let target_int = U256::from_str_radix(target, 16).expect("Invalid target hex string");
     let hash_int = U256::from_str_radix(hash, 16).expect("Invalid hash hex string");
     hash_int < target_int
+}
+
+// Share difficulty is expressed as the expected number of hashes needed to
+// find a share, i.e. relative to a target of 2^256 - 1.
+pub fn target_to_difficulty(target: &str) -> f64 {
+    let target_int = U256::from_str_radix(target, 16).expect("Invalid target hex string");
+    if target_int.is_zero() {
+        return f64::INFINITY;
+    }
+    u256_to_f64(U256::MAX) / u256_to_f64(target_int)
+}
+
+pub fn difficulty_to_target(difficulty: f64) -> Option<String> {
+    if !difficulty.is_finite() || difficulty <= 0.0 {
+        return None;
+    }
+    if difficulty <= 1.0 {
+        return Some(format!("{:064x}", U256::MAX));
+    }
+    // Keep 16 fractional bits of the difficulty so non-integer vardiff
+    // values still produce distinct targets.
+    let scaled = (difficulty * 65536.0).min(u128::MAX as f64) as u128;
+    let target = (U256::MAX / U256::from(scaled)) << 16;
+    Some(format!("{:064x}", target))
+}
+
+fn u256_to_f64(value: U256) -> f64 {
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, &limb| acc * 18446744073709551616.0 + limb as f64)
 }
\ No newline at end of file
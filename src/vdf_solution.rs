@@ -11,16 +11,17 @@ pub struct HCGraphUtil {
 
 impl HCGraphUtil {
     pub fn new(vdf_bailout: Option<u64>) -> Self {
-        let bailout_timer: u64 = match vdf_bailout {
-            Some(timer) => timer,
-            None => 1000, // default to 1 second
-        };
+        let bailout_timer: u64 = vdf_bailout.unwrap_or(1000); // default to 1 second
         HCGraphUtil {
             start_time: Instant::now(),
             vdf_bailout: bailout_timer,
         }
     }
 
+    pub fn set_bailout(&mut self, vdf_bailout: u64) {
+        self.vdf_bailout = vdf_bailout;
+    }
+
     fn hex_to_u64(&self, hex_string: &str) -> u64 {
         u64::from_str_radix(hex_string, 16).expect("Failed to convert hex to u64")
     }
@@ -54,6 +55,7 @@ impl HCGraphUtil {
         grid_size_final as u16
     }
 
+    #[allow(clippy::needless_range_loop)]
     fn generate_graph_v2(&self, hash: &U256, grid_size: u16) -> Vec<Vec<bool>> {
         let grid_size = grid_size as usize;
         let mut graph = vec![vec![false; grid_size]; grid_size];
@@ -89,6 +91,7 @@ impl HCGraphUtil {
         graph
     }
 
+    #[allow(clippy::needless_range_loop)]
     fn _opt(&self, hash: &U256, grid_size: u16) -> Vec<Vec<bool>> {
         let grid_size = grid_size as usize;
         let mut graph = vec![vec![false; grid_size]; grid_size];
@@ -118,21 +121,15 @@ impl HCGraphUtil {
         graph
     }
 
-    fn is_safe(&self, v: u16, graph: &Vec<Vec<bool>>, path: &[u16], pos: usize) -> bool {
+    fn is_safe(&self, v: u16, graph: &[Vec<bool>], path: &[u16], pos: usize) -> bool {
         if !graph[path[pos - 1] as usize][v as usize] {
             return false;
         }
 
-        for i in 0..pos {
-            if path[i] == v {
-                return false;
-            }
-        }
-
-        true
+        !path[..pos].contains(&v)
     }
 
-    fn is_safe_vp(&self, v: u16, graph: &Vec<Vec<bool>>, path: &[u16], pos: usize) -> bool {
+    fn is_safe_vp(&self, v: u16, graph: &[Vec<bool>], path: &[u16], pos: usize) -> bool {
         if pos == 0 || !graph[path[pos - 1] as usize][v as usize] {
             return false;
         }
@@ -148,7 +145,7 @@ impl HCGraphUtil {
 
     fn hamiltonian_cycle_util(
         &mut self,
-        graph: &Vec<Vec<bool>>,
+        graph: &[Vec<bool>],
         path: &mut [u16],
         pos: usize,
     ) -> bool {
@@ -178,9 +175,9 @@ impl HCGraphUtil {
 
     fn hamiltonian_cycle_util_vp(
         &mut self,
-        graph: &Vec<Vec<bool>>,
+        graph: &[Vec<bool>],
         path: &mut [u16],
-        visited: &mut Vec<bool>,
+        visited: &mut [bool],
     ) -> bool {
         let mut position_vertex_stack: Vec<(usize, usize)> = Vec::new();
         let mut pos = 1;
@@ -264,4 +261,30 @@ impl HCGraphUtil {
         }
         path
     }
+
+    // Checks a submitted path against the graph for `graph_hash` without
+    // searching, so it is cheap enough to run on every share.
+    pub fn verify_hamiltonian_cycle(&self, graph_hash: U256, path: &[u16]) -> bool {
+        let grid_size = self.get_grid_size_v2(&graph_hash);
+        let graph = self.generate_graph_v2(&graph_hash, grid_size);
+        let n = graph.len();
+
+        if path.len() < n || path[0] != 0 {
+            return false;
+        }
+        if path[n..].iter().any(|&v| v != u16::MAX) {
+            return false;
+        }
+
+        let mut visited = vec![false; n];
+        for &v in &path[..n] {
+            let v = v as usize;
+            if v >= n || visited[v] {
+                return false;
+            }
+            visited[v] = true;
+        }
+
+        (0..n).all(|i| graph[path[i] as usize][path[(i + 1) % n] as usize])
+    }
 }
@@ -0,0 +1,290 @@
+// Picks the cycle search bailout for `--vdftime auto`. Every search is
+// recorded with how long it ran, whether it solved and the bailout it ran
+// under. A candidate bailout B is scored on the searches that ran at least
+// B: those that solved within B, per second of search time spent when each
+// search is cut off at B. Most solvable graphs finish quickly, so cutting
+// the long tail usually buys more solves than it loses.
+//
+// Searches cut off at the current choice say nothing about longer bailouts,
+// so one search in EXPLORE_EVERY runs at twice the choice. That keeps the
+// tail above it visible and lets the choice grow again.
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const EVALUATE_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_SAMPLES: usize = 5000;
+// Solves a candidate must keep before it is trusted over the current choice.
+const MIN_SOLVES: usize = 50;
+const EXPLORE_EVERY: usize = 20;
+const MIN_BAILOUT_MS: u64 = 10;
+const MAX_BAILOUT_MS: u64 = 10_000;
+const MAX_CANDIDATES: usize = 200;
+// A new choice must beat the current one by this much, so noise between
+// evaluations does not make it wander.
+const MIN_GAIN: f64 = 1.02;
+
+struct Sample {
+    duration_ms: f64,
+    solved: bool,
+    bailout_ms: u64,
+}
+
+// A bailout's score over the recorded searches that ran at least as long.
+#[derive(Clone, Copy)]
+struct Score {
+    bailout_ms: u64,
+    solved: usize,
+    // Solves those searches made under their own, longer, bailouts.
+    reachable: usize,
+    search_secs: f64,
+}
+
+impl Score {
+    fn rate(&self) -> f64 {
+        if self.search_secs > 0.0 { self.solved as f64 / self.search_secs } else { 0.0 }
+    }
+}
+
+// The last evaluation, for `/stats`.
+#[derive(Serialize, Clone)]
+pub struct VdfTuneStatus {
+    pub chosen_ms: u64,
+    pub exploring_ms: u64,
+    pub samples: usize,
+    pub solved_samples: usize,
+    // Solved cycles per second of search time at the chosen bailout.
+    pub solved_per_search_second: f64,
+    // Share of the solves seen in the scored searches that the chosen bailout keeps.
+    pub kept_solves: f64,
+    pub candidates: usize,
+    pub evaluated: u64,
+    pub reason: String,
+}
+
+pub struct VdfTuner {
+    samples: Mutex<VecDeque<Sample>>,
+    chosen_ms: AtomicU64,
+    searches: AtomicUsize,
+    status: Mutex<Option<VdfTuneStatus>>,
+}
+
+impl VdfTuner {
+    pub fn new(initial_ms: u64) -> VdfTuner {
+        VdfTuner {
+            samples: Mutex::new(VecDeque::with_capacity(MAX_SAMPLES)),
+            chosen_ms: AtomicU64::new(initial_ms),
+            searches: AtomicUsize::new(0),
+            status: Mutex::new(None),
+        }
+    }
+
+    pub fn chosen_ms(&self) -> u64 {
+        self.chosen_ms.load(Ordering::Relaxed)
+    }
+
+    fn exploring_ms(&self) -> u64 {
+        (self.chosen_ms() * 2).min(MAX_BAILOUT_MS)
+    }
+
+    // The bailout the next search should run under.
+    pub fn bailout_for_search(&self) -> u64 {
+        if self.searches.fetch_add(1, Ordering::Relaxed).is_multiple_of(EXPLORE_EVERY) {
+            self.exploring_ms()
+        } else {
+            self.chosen_ms()
+        }
+    }
+
+    pub fn record(&self, duration: Duration, solved: bool, bailout_ms: u64) {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(Sample { duration_ms: duration.as_secs_f64() * 1000.0, solved, bailout_ms });
+    }
+
+    pub fn status(&self) -> Option<VdfTuneStatus> {
+        self.status.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // Scores candidate bailouts on the recorded searches and switches to the
+    // best one. Returns the previous bailout when the choice changed.
+    pub fn evaluate(&self) -> Option<u64> {
+        let samples: Vec<(f64, bool, u64)> = self
+            .samples
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|sample| (sample.duration_ms, sample.solved, sample.bailout_ms))
+            .collect();
+        let current_ms = self.chosen_ms();
+        let solved_samples = samples.iter().filter(|(_, solved, _)| *solved).count();
+
+        let mut candidates = candidate_bailouts(&samples);
+        candidates.push(current_ms);
+        candidates.sort_unstable();
+        candidates.dedup();
+        let scores: Vec<Score> = candidates.iter().map(|bailout_ms| score(&samples, *bailout_ms)).collect();
+        let current = score(&samples, current_ms);
+        let best = scores
+            .iter()
+            .filter(|score| score.solved >= MIN_SOLVES)
+            .max_by(|a, b| a.rate().total_cmp(&b.rate()))
+            .copied();
+
+        let (chosen, reason) = match best {
+            None => (
+                current,
+                format!("collecting samples: {} of {} solves needed before choosing", solved_samples, MIN_SOLVES),
+            ),
+            Some(best) if best.bailout_ms != current_ms && (current.solved < MIN_SOLVES || best.rate() > current.rate() * MIN_GAIN) => (
+                best,
+                format!(
+                    "{} ms solves {:.2} cycles per search second against {:.2} at {} ms, keeping {:.0}% of the solves",
+                    best.bailout_ms,
+                    best.rate(),
+                    current.rate(),
+                    current_ms,
+                    kept(&best) * 100.0
+                ),
+            ),
+            Some(best) if best.bailout_ms == current_ms => (
+                current,
+                format!("{} ms is the best of {} candidates at {:.2} cycles per search second", current_ms, candidates.len(), best.rate()),
+            ),
+            Some(best) => (
+                current,
+                format!(
+                    "{} ms is within {:.0}% of the best of {} candidates ({} ms at {:.2} cycles per search second)",
+                    current_ms,
+                    (MIN_GAIN - 1.0) * 100.0,
+                    candidates.len(),
+                    best.bailout_ms,
+                    best.rate()
+                ),
+            ),
+        };
+
+        self.chosen_ms.store(chosen.bailout_ms, Ordering::Relaxed);
+        *self.status.lock().unwrap_or_else(|e| e.into_inner()) = Some(VdfTuneStatus {
+            chosen_ms: chosen.bailout_ms,
+            exploring_ms: self.exploring_ms(),
+            samples: samples.len(),
+            solved_samples,
+            solved_per_search_second: (chosen.rate() * 100.0).round() / 100.0,
+            kept_solves: (kept(&chosen) * 1000.0).round() / 1000.0,
+            candidates: candidates.len(),
+            evaluated: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()),
+            reason,
+        });
+        (chosen.bailout_ms != current_ms).then_some(current_ms)
+    }
+}
+
+// The score only changes at solve times, so the best bailout is always just
+// at one of them; spread candidates over the solve times seen.
+fn candidate_bailouts(samples: &[(f64, bool, u64)]) -> Vec<u64> {
+    let mut solve_ms: Vec<u64> = samples
+        .iter()
+        .filter(|(_, solved, _)| *solved)
+        .map(|(duration_ms, _, _)| (duration_ms.ceil() as u64).clamp(MIN_BAILOUT_MS, MAX_BAILOUT_MS))
+        .collect();
+    solve_ms.sort_unstable();
+    solve_ms.dedup();
+    let step = solve_ms.len().div_ceil(MAX_CANDIDATES).max(1);
+    solve_ms.into_iter().step_by(step).collect()
+}
+
+// What the recorded searches would have yielded had they been cut off at
+// `bailout_ms`. Only searches that ran under at least that bailout tell.
+fn score(samples: &[(f64, bool, u64)], bailout_ms: u64) -> Score {
+    let limit = bailout_ms as f64;
+    let mut score = Score { bailout_ms, solved: 0, reachable: 0, search_secs: 0.0 };
+    for &(duration_ms, solved, _) in samples.iter().filter(|(_, _, sample_bailout_ms)| *sample_bailout_ms >= bailout_ms) {
+        if solved {
+            score.reachable += 1;
+            if duration_ms <= limit {
+                score.solved += 1;
+            }
+        }
+        score.search_secs += duration_ms.min(limit) / 1000.0;
+    }
+    score
+}
+
+fn kept(score: &Score) -> f64 {
+    if score.reachable == 0 { 0.0 } else { score.solved as f64 / score.reachable as f64 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(tuner: &VdfTuner, count: usize, duration_ms: u64, solved: bool) {
+        for _ in 0..count {
+            tuner.record(Duration::from_millis(duration_ms), solved, 1000);
+        }
+    }
+
+    #[test]
+    fn waits_for_enough_solves() {
+        let tuner = VdfTuner::new(1000);
+        record(&tuner, MIN_SOLVES - 1, 20, true);
+        record(&tuner, 100, 1000, false);
+        assert_eq!(tuner.evaluate(), None);
+        assert_eq!(tuner.chosen_ms(), 1000);
+        assert!(tuner.status().unwrap().reason.starts_with("collecting samples"));
+
+        record(&tuner, 1, 20, true);
+        assert_eq!(tuner.evaluate(), Some(1000));
+        assert_eq!(tuner.chosen_ms(), 20);
+    }
+
+    #[test]
+    fn cuts_off_the_long_tail() {
+        // Most solves come quickly, a few late, and the rest of the
+        // searches run into the bailout.
+        let tuner = VdfTuner::new(1000);
+        record(&tuner, 100, 20, true);
+        record(&tuner, 10, 800, true);
+        record(&tuner, 100, 1000, false);
+        assert_eq!(tuner.evaluate(), Some(1000));
+        assert_eq!(tuner.chosen_ms(), 20);
+
+        let status = tuner.status().unwrap();
+        assert_eq!((status.chosen_ms, status.exploring_ms), (20, 40));
+        assert_eq!((status.samples, status.solved_samples), (210, 110));
+        assert_eq!(status.kept_solves, 0.909);
+        assert_eq!(status.solved_per_search_second, 23.81);
+    }
+
+    #[test]
+    fn small_gains_keep_the_current_choice() {
+        // 50 ms beats 60 ms by under MIN_GAIN when few searches run long.
+        let tuner = VdfTuner::new(60);
+        record(&tuner, 100, 50, true);
+        record(&tuner, 10, 1000, false);
+        assert_eq!(tuner.evaluate(), None);
+        assert_eq!(tuner.chosen_ms(), 60);
+        assert!(tuner.status().unwrap().reason.contains("within 2% of the best"));
+
+        // More long searches make the gain worth taking.
+        record(&tuner, 20, 1000, false);
+        assert_eq!(tuner.evaluate(), Some(60));
+        assert_eq!(tuner.chosen_ms(), 50);
+    }
+
+    #[test]
+    fn explores_twice_the_choice() {
+        let tuner = VdfTuner::new(100);
+        let bailouts: Vec<u64> = (0..2 * EXPLORE_EVERY).map(|_| tuner.bailout_for_search()).collect();
+        assert_eq!(bailouts.iter().filter(|bailout_ms| **bailout_ms == 200).count(), 2);
+        assert_eq!(bailouts.iter().filter(|bailout_ms| **bailout_ms == 100).count(), 2 * EXPLORE_EVERY - 2);
+
+        let tuner = VdfTuner::new(MAX_BAILOUT_MS);
+        assert_eq!(tuner.bailout_for_search(), MAX_BAILOUT_MS);
+    }
+}
@@ -0,0 +1,269 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
+use colored::*;
+use crate::affinity::{current_cpu, lower_current_thread_priority, pin_current_thread, CpuList};
+use crate::api::{MinerState, PendingShare, WorkerCounters};
+use crate::numa::NumaNode;
+use crate::hasher::{compute_hash_no_vdf, compute_hash_no_vdf_verify};
+use crate::models::{Job, SubmitMessage};
+use crate::utils::{generate_nonce, generate_nonce_in, meets_target, target_to_difficulty};
+use crate::vdf_solution::HCGraphUtil;
+
+// Longest single sleep of the --max-cpu pause, so a stopped worker exits promptly.
+const THROTTLE_SLICE: Duration = Duration::from_millis(100);
+
+// Everything a worker thread needs; cloned into each one.
+#[derive(Clone)]
+pub struct WorkerContext {
+    pub current_job: Arc<Mutex<Option<Job>>>,
+    pub submissions: UnboundedSender<String>,
+    pub miner_id: String,
+    pub miner_state: Arc<MinerState>,
+    pub hash_count: Arc<AtomicUsize>,
+    pub bailout_timer: Option<u64>,
+    // Run at nice 19 / SCHED_IDLE.
+    pub background: bool,
+    // Fraction of wall time spent searching, from --max-cpu.
+    pub max_cpu: Option<f64>,
+    // Index into `MinerState::numa_nodes` when workers are placed by node.
+    pub node: Option<usize>,
+}
+
+// How workers are spread over the machine.
+#[derive(Clone, Debug)]
+pub enum Placement {
+    Unpinned,
+    // Worker N runs on the Nth CPU of the list.
+    Cpus(CpuList),
+    // Worker N runs on node N modulo the node count, on any of its CPUs.
+    Numa(Vec<NumaNode>),
+}
+
+impl Placement {
+    pub fn cpu_count(&self) -> usize {
+        match self {
+            Placement::Unpinned => num_cpus::get(),
+            Placement::Cpus(cpus) => cpus.len(),
+            Placement::Numa(nodes) => nodes.iter().map(|node| node.cpus.len()).sum(),
+        }
+    }
+
+    // The CPUs the worker at `index` is pinned to, and its node slot.
+    fn for_worker(&self, index: usize) -> (Option<Vec<usize>>, Option<usize>) {
+        match self {
+            Placement::Unpinned => (None, None),
+            Placement::Cpus(cpus) => (Some(vec![cpus.for_worker(index)]), None),
+            Placement::Numa(nodes) => {
+                let slot = index % nodes.len();
+                (Some(nodes[slot].cpus.0.clone()), Some(slot))
+            }
+        }
+    }
+}
+
+// The set of running worker threads. Workers are retired from the end by
+// raising their stop flag; they exit after the cycle search in progress.
+pub struct WorkerPool {
+    context: WorkerContext,
+    placement: Placement,
+    workers: Vec<(Arc<AtomicBool>, Option<usize>)>,
+}
+
+impl WorkerPool {
+    pub fn new(context: WorkerContext, placement: Placement) -> WorkerPool {
+        WorkerPool { context, placement, workers: Vec::new() }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn resize(&mut self, count: usize) {
+        while self.workers.len() < count {
+            let index = self.workers.len();
+            let (cpus, node) = self.placement.for_worker(index);
+            let stop = Arc::new(AtomicBool::new(false));
+            let context = WorkerContext { node, ..self.context.clone() };
+            let counters = self.context.miner_state.worker_counters(index);
+            counters.running.store(true, Ordering::Relaxed);
+            *counters.cpus.lock().unwrap_or_else(|e| e.into_inner()) = cpus.as_ref().map(|cpus| CpuList(cpus.clone()).to_string());
+            let worker_stop = stop.clone();
+            // Pin before the worker allocates anything: the graphs each search
+            // builds are then first touched, and so placed, on its own node.
+            thread::spawn(move || {
+                if let Some(cpus) = cpus {
+                    if let Err(e) = pin_current_thread(&cpus) {
+                        println!("{}", format!("Failed to pin worker {} to CPUs {}: {}", index, CpuList(cpus), e).red());
+                    }
+                }
+                if context.background {
+                    if let Err(e) = lower_current_thread_priority() {
+                        println!("{}", format!("Failed to lower the priority of worker {}: {}", index, e).red());
+                    }
+                }
+                run_worker(context, counters, worker_stop)
+            });
+            if let Some(node) = node {
+                self.context.miner_state.numa_nodes[node].workers.fetch_add(1, Ordering::Relaxed);
+            }
+            self.workers.push((stop, node));
+        }
+        while self.workers.len() > count {
+            if let Some((stop, node)) = self.workers.pop() {
+                stop.store(true, Ordering::Relaxed);
+                self.context.miner_state.worker_counters(self.workers.len()).running.store(false, Ordering::Relaxed);
+                if let Some(node) = node {
+                    self.context.miner_state.numa_nodes[node].workers.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+        }
+        self.context.miner_state.workers.store(count, Ordering::Relaxed);
+    }
+}
+
+fn run_worker(context: WorkerContext, counters: Arc<WorkerCounters>, stop: Arc<AtomicBool>) {
+    let WorkerContext { current_job, submissions, miner_id, miner_state, hash_count, bailout_timer, max_cpu, node, .. } = context;
+    let mut hc_util = HCGraphUtil::new(bailout_timer);
+    let mut hc_util_verify = HCGraphUtil::new(bailout_timer);
+    // The --max-cpu pause owed for the last search. It is taken just before
+    // the next search, so a found share is submitted and a new job picked up
+    // first, and the vdf bailout clock only ever measures search time.
+    let mut throttle = Duration::ZERO;
+    while !stop.load(Ordering::Relaxed) {
+        let job_option = {
+            let job_guard = current_job.blocking_lock();
+            job_guard.clone()
+        };
+
+        if let Some(mut job) = job_option {
+            while !stop.load(Ordering::Relaxed) {
+                sleep_unless_stopped(std::mem::take(&mut throttle), &stop);
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let nonce = match job.nonce_range {
+                    Some((start, count)) => generate_nonce_in(start, count),
+                    None => generate_nonce(),
+                };
+
+                let bailout_ms = match &miner_state.vdf_tuner {
+                    Some(tuner) => {
+                        let bailout_ms = tuner.bailout_for_search();
+                        hc_util.set_bailout(bailout_ms);
+                        hc_util_verify.set_bailout(bailout_ms);
+                        bailout_ms
+                    }
+                    None => miner_state.vdftime_ms.load(Ordering::Relaxed),
+                };
+                let search_started = Instant::now();
+                let solution = compute_hash_no_vdf(&("".to_owned() + &job.data + &nonce), &mut hc_util);
+                let search_time = search_started.elapsed();
+                miner_state.attempts.fetch_add(1, Ordering::Relaxed);
+                counters.attempts.fetch_add(1, Ordering::Relaxed);
+                if let Some(tuner) = &miner_state.vdf_tuner {
+                    tuner.record(search_time, solution.is_some(), bailout_ms);
+                }
+                match solution {
+                    Some(_) => {
+                        miner_state.solve_times.observe(search_time);
+                        miner_state.solve_budget.observe_value(search_time.as_secs_f64() * 1000.0 / bailout_ms.max(1) as f64);
+                        counters.solve_micros.fetch_add(search_time.as_micros() as u64, Ordering::Relaxed);
+                    }
+                    None => {
+                        miner_state.bailouts.fetch_add(1, Ordering::Relaxed);
+                        counters.bailouts.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                counters.last_activity.store(unix_time(), Ordering::Relaxed);
+                if let Some(cpu) = current_cpu() {
+                    counters.last_cpu.store(cpu, Ordering::Relaxed);
+                }
+                if let Some(fraction) = max_cpu.filter(|fraction| *fraction < 1.0) {
+                    throttle = search_time.mul_f64(1.0 / fraction - 1.0);
+                }
+
+                if let Some((hash, path_hex)) = solution {
+                    hash_count.fetch_add(1, Ordering::Relaxed);
+                    miner_state.hash_count.fetch_add(1, Ordering::Relaxed);
+                    counters.hash_count.fetch_add(1, Ordering::Relaxed);
+                    if let Some(node) = node {
+                        miner_state.numa_nodes[node].hash_count.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    if meets_target(&hash, &job.target) {
+                        if let Some((_hash_v, _path_hex_v)) = compute_hash_no_vdf_verify(&("".to_owned() + &job.data + &nonce), &mut hc_util_verify) {
+                            // The pool may have raised the share target while this
+                            // cycle was being solved; such a share would only be rejected.
+                            let current_target = {
+                                let job_guard = current_job.blocking_lock();
+                                job_guard.as_ref().filter(|j| j.job_id == job.job_id).map(|j| j.target.clone())
+                            };
+                            if let Some(target) = current_target.as_ref().filter(|t| **t != job.target) {
+                                if !meets_target(&hash, target) {
+                                    miner_state.stale_target_shares.fetch_add(1, Ordering::Relaxed);
+                                    job.target = target.clone();
+                                    continue;
+                                }
+                            }
+
+                            if meets_target(&hash, &job.target) {
+                                miner_state.pending_shares.blocking_lock().push_back(PendingShare {
+                                    difficulty: target_to_difficulty(current_target.as_ref().unwrap_or(&job.target)),
+                                    submitted: Instant::now(),
+                                });
+
+                                let submit_msg = SubmitMessage {
+                                    r#type: String::from("submit"),
+                                    miner_id: miner_id.to_string(),
+                                    nonce,
+                                    job_id: job.job_id.clone(),
+                                    path: path_hex,
+                                };
+
+                                let msg = serde_json::to_string(&submit_msg).unwrap();
+                                let _ = submissions.send(msg);
+                                counters.shares.fetch_add(1, Ordering::Relaxed);
+
+                                let mut job_guard = current_job.blocking_lock();
+                                *job_guard = None;
+                                break;
+                            }
+                        }
+                    }
+
+                    // Check if there's a new job
+                    let new_job_option = {
+                        let job_guard = current_job.blocking_lock();
+                        job_guard.clone()
+                    };
+
+                    match new_job_option {
+                        // Same job with a new share target from vardiff: keep going.
+                        Some(new_job) if new_job.job_id == job.job_id => job.target = new_job.target,
+                        _ => break,
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn sleep_unless_stopped(duration: Duration, stop: &AtomicBool) {
+    let deadline = Instant::now() + duration;
+    while !stop.load(Ordering::Relaxed) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        thread::sleep(remaining.min(THROTTLE_SLICE));
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
@@ -0,0 +1,237 @@
+// Runs the miner against shaipot-mockpool on an ephemeral port and checks
+// how it reacts to what the pool scripts: shares, close codes, bad frames
+// and job churn.
+use shaipot::exit_codes;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+const ADDRESS: &str = "sh1qeexkz69dz6j4q0zt0pkn36650yevwc8eksqeuu";
+// A second address, for a rig mining through the proxy.
+const RIG_ADDRESS: &str = "sh1qzyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg30zty46";
+// Generous, since a debug build takes seconds per graph search and the
+// tests' miners share the machine.
+const TIMEOUT: Duration = Duration::from_secs(180);
+
+// A child process whose output lines are read on a separate thread; it is
+// killed when dropped so a failing test does not leave it behind.
+struct Process {
+    child: Child,
+    lines: Receiver<String>,
+    seen: Vec<String>,
+}
+
+impl Process {
+    fn spawn(program: &str, args: &[&str]) -> Process {
+        let mut child = Command::new(program)
+            .args(args)
+            .env("NO_COLOR", "1")
+            .env("CLICOLOR", "0")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|e| panic!("failed to start {}: {}", program, e));
+        let stdout = child.stdout.take().unwrap();
+        let (sender, lines) = channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(strip_ansi(&line)).is_err() {
+                    break;
+                }
+            }
+        });
+        Process { child, lines, seen: Vec::new() }
+    }
+
+    // Waits for `count` lines (counted from the start) containing `needle`.
+    fn wait_for_count(&mut self, needle: &str, count: usize) -> String {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let matching: Vec<&String> = self.seen.iter().filter(|line| line.contains(needle)).collect();
+            if matching.len() >= count {
+                return matching[count - 1].clone();
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.lines.recv_timeout(remaining) {
+                Ok(line) => self.seen.push(line),
+                Err(_) => panic!("timed out waiting for {} x '{}'; output so far:\n{}", count, needle, self.seen.join("\n")),
+            }
+        }
+    }
+
+    fn wait_for(&mut self, needle: &str) -> String {
+        self.wait_for_count(needle, 1)
+    }
+
+    fn wait_exit(&mut self) -> ExitStatus {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return status;
+            }
+            assert!(Instant::now() < deadline, "process did not exit; output so far:\n{}", self.seen.join("\n"));
+            while let Ok(line) = self.lines.try_recv() {
+                self.seen.push(line);
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// Starts the mock pool with `extra` arguments and returns it with its ws:// URL.
+fn start_pool(extra: &[&str]) -> (Process, String) {
+    let mut args = vec!["--bind", "127.0.0.1:0"];
+    args.extend_from_slice(extra);
+    let mut pool = Process::spawn(env!("CARGO_BIN_EXE_shaipot-mockpool"), &args);
+    let line = pool.wait_for("Mock pool listening on");
+    let url = line.rsplit(' ').next().unwrap().trim().to_string();
+    (pool, url)
+}
+
+fn start_miner(url: &str) -> Process {
+    start_miner_as(url, ADDRESS)
+}
+
+fn start_miner_as(url: &str, address: &str) -> Process {
+    Process::spawn(
+        env!("CARGO_BIN_EXE_shaipot"),
+        &[
+            "--address",
+            address,
+            "--pool",
+            url,
+            "--threads",
+            "1",
+            "--vdftime",
+            "30",
+            "--no-api",
+            "--backoff-initial",
+            "0.5",
+            "--backoff-max",
+            "1",
+        ],
+    )
+}
+
+// Starts a proxy in front of the pool and returns it with its ws:// URL.
+fn start_proxy(pool_url: &str) -> (Process, String) {
+    let mut proxy = Process::spawn(
+        env!("CARGO_BIN_EXE_shaipot"),
+        &["proxy", "--address", ADDRESS, "--pool", pool_url, "--listen", "127.0.0.1:0", "--backoff-initial", "0.5", "--backoff-max", "1"],
+    );
+    let line = proxy.wait_for("PROXY LISTENING ON");
+    let url = line.rsplit(' ').next().unwrap().trim().to_string();
+    (proxy, url)
+}
+
+#[test]
+fn share_is_submitted_and_accepted() {
+    let (mut pool, url) = start_pool(&["--script", "job"]);
+    let _miner = start_miner(&url);
+    pool.wait_for("connected");
+    pool.wait_for("login ");
+    let accepted = pool.wait_for("accepted ");
+    assert!(accepted.contains("for job 1"), "unexpected share line: {}", accepted);
+}
+
+#[test]
+fn close_1012_reconnects() {
+    let (mut pool, url) = start_pool(&["--script", "job,wait:1,close:1012:restart"]);
+    let _miner = start_miner(&url);
+    pool.wait_for("connected");
+    pool.wait_for("disconnected");
+    pool.wait_for_count(" connected", 2);
+    pool.wait_for_count("login ", 2);
+}
+
+#[test]
+fn close_4001_exits_banned() {
+    let (_pool, url) = start_pool(&["--script", "job,wait:1,close:4001:banned"]);
+    let mut miner = start_miner(&url);
+    let status = miner.wait_exit();
+    assert_eq!(status.code(), Some(exit_codes::POOL_BANNED));
+}
+
+#[test]
+fn malformed_and_unknown_frames_are_survived() {
+    let (mut pool, url) = start_pool(&["--script", "malformed,unknown,wait:0.5,job"]);
+    let mut miner = start_miner(&url);
+    pool.wait_for("accepted ");
+    assert!(miner.child.try_wait().unwrap().is_none(), "the miner exited on a bad frame");
+    // Still on the first connection.
+    assert_eq!(pool.seen.iter().filter(|line| line.ends_with(" connected")).count(), 1);
+}
+
+#[test]
+fn job_churn_is_handled() {
+    let (mut pool, url) = start_pool(&["--script", "job,job,job", "--job-interval", "0.3"]);
+    let mut miner = start_miner(&url);
+    pool.wait_for_count("accepted ", 3);
+    assert!(miner.child.try_wait().unwrap().is_none(), "the miner exited during job churn");
+    assert!(!pool.seen.iter().any(|line| line.contains("unknown job")), "a share was sent for a job the pool never issued");
+}
+
+#[test]
+fn job_with_invalid_target_is_skipped() {
+    let valid = "f".repeat(64);
+    let script = format!("target:zz,job,wait:1,target:{},job", valid);
+    let (mut pool, url) = start_pool(&["--script", &script]);
+    let mut miner = start_miner(&url);
+    let accepted = pool.wait_for("accepted ");
+    assert!(accepted.contains("for job 2"), "unexpected share line: {}", accepted);
+    assert!(miner.child.try_wait().unwrap().is_none(), "the miner exited on a bad job target");
+}
+
+#[test]
+fn proxy_exits_when_the_pool_bans_it() {
+    let (mut pool, url) = start_pool(&["--script", "job,wait:1,close:4001:banned"]);
+    let (mut proxy, _) = start_proxy(&url);
+    pool.wait_for("login ");
+    assert_eq!(proxy.wait_exit().code(), Some(exit_codes::POOL_BANNED));
+}
+
+#[test]
+fn rejected_login_exits() {
+    let (_pool, url) = start_pool(&["--script", "job", "--reject-login", "unknown worker"]);
+    let mut miner = start_miner(&url);
+    assert_eq!(miner.wait_exit().code(), Some(exit_codes::POOL_LOGIN_REJECTED));
+}
+
+// The pool only knows the proxy's address, so a rig's shares must be
+// credited to it rather than to the rig's own address.
+#[test]
+fn proxy_submits_under_its_own_address() {
+    let (mut pool, url) = start_pool(&["--script", "job"]);
+    let (_proxy, proxy_url) = start_proxy(&url);
+    pool.wait_for("login ");
+    let _rig = start_miner_as(&proxy_url, RIG_ADDRESS);
+    pool.wait_for("accepted ");
+    assert!(!pool.seen.iter().any(|line| line.contains("rejected")), "output:\n{}", pool.seen.join("\n"));
+    assert!(pool.seen.iter().filter(|line| line.contains("login ")).all(|line| line.contains(ADDRESS)));
+}
@@ -0,0 +1,141 @@
+// Drives solo mining against a mock node that serves getblocktemplate and
+// records submitblock calls, so the whole template -> job -> block path is
+// checked without a real shaicoind.
+use base64::Engine;
+use serde_json::{json, Value};
+use shaipot::address::{decode_address, MAINNET_HRP};
+use shaipot::api::MinerState;
+use shaipot::connection::{Backoff, ConnectionTracker};
+use shaipot::hasher::{compute_hash_no_vdf, verify_solution};
+use shaipot::models::Job;
+use shaipot::rpc::RpcClient;
+use shaipot::solo::run_solo;
+use shaipot::utils::generate_nonce;
+use shaipot::vdf_solution::HCGraphUtil;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::Mutex;
+use warp::Filter;
+
+const ADDRESS: &str = "sh1qeexkz69dz6j4q0zt0pkn36650yevwc8eksqeuu";
+const WITNESS_COMMITMENT: &str = "6a24aa21a9ed0000000000000000000000000000000000000000000000000000000000000000";
+const TEMPLATE_TX: &str = "deadbeef";
+
+#[derive(Default)]
+struct MockNode {
+    blocks: Mutex<Vec<String>>,
+    authorization: Mutex<Option<String>>,
+}
+
+impl MockNode {
+    async fn start() -> (SocketAddr, Arc<MockNode>) {
+        let node = Arc::new(MockNode::default());
+        let state = node.clone();
+        let route = warp::post()
+            .and(warp::header::optional::<String>("authorization"))
+            .and(warp::body::json())
+            .and_then(move |authorization: Option<String>, request: Value| {
+                let node = state.clone();
+                async move { Ok::<_, warp::Rejection>(warp::reply::json(&node.handle(authorization, request).await)) }
+            });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (addr, node)
+    }
+
+    async fn handle(&self, authorization: Option<String>, request: Value) -> Value {
+        *self.authorization.lock().await = authorization;
+        let result = match request["method"].as_str() {
+            Some("getblocktemplate") => {
+                let height = 100 + self.blocks.lock().await.len() as u64;
+                json!({
+                    "version": 0x20000000,
+                    "previousblockhash": format!("{:064x}", height),
+                    "transactions": [{ "data": TEMPLATE_TX, "txid": "11".repeat(32) }],
+                    "coinbasevalue": 5_000_000_000u64,
+                    "default_witness_commitment": WITNESS_COMMITMENT,
+                    "target": "f".repeat(64),
+                    "curtime": 1_700_000_000,
+                    "bits": "207fffff",
+                    "height": height,
+                })
+            }
+            Some("submitblock") => {
+                self.blocks.lock().await.push(request["params"][0].as_str().unwrap_or_default().to_string());
+                Value::Null
+            }
+            _ => return json!({ "result": null, "error": { "code": -32601, "message": "Method not found" }, "id": request["id"] }),
+        };
+        json!({ "result": result, "error": null, "id": request["id"] })
+    }
+}
+
+// Plays the part of a worker: waits for a job and solves it once.
+fn solve(current_job: Arc<Mutex<Option<Job>>>) -> (Job, String, String) {
+    let mut hc_util = HCGraphUtil::new(Some(30_000));
+    let deadline = Instant::now() + Duration::from_secs(60);
+    while Instant::now() < deadline {
+        let Some(job) = current_job.blocking_lock().clone() else {
+            std::thread::sleep(Duration::from_millis(50));
+            continue;
+        };
+        let nonce = generate_nonce();
+        if let Some((_, path)) = compute_hash_no_vdf(&format!("{}{}", job.data, nonce), &mut hc_util) {
+            return (job, nonce, path);
+        }
+    }
+    panic!("no job was solved within a minute");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn solo_block_is_built_and_submitted() {
+    let (addr, node) = MockNode::start().await;
+    let rpc = RpcClient::new(&format!("http://miner:p%40ss@{}/", addr)).unwrap();
+    let payout_script = decode_address(ADDRESS, MAINNET_HRP).unwrap().script_pubkey();
+
+    let current_job: Arc<Mutex<Option<Job>>> = Arc::new(Mutex::new(None));
+    let (submissions, submission_receiver) = unbounded_channel::<String>();
+    let miner_state = Arc::new(MinerState::default());
+    let connection = ConnectionTracker::new(miner_state.connection.clone(), Backoff::new(Duration::from_secs(1), Duration::from_secs(2)), None);
+    tokio::spawn(run_solo(
+        rpc,
+        payout_script.clone(),
+        current_job.clone(),
+        submission_receiver,
+        miner_state.clone(),
+        connection,
+        Duration::from_secs(1),
+    ));
+
+    let worker_job = current_job.clone();
+    let (job, nonce, path) = tokio::task::spawn_blocking(move || solve(worker_job)).await.unwrap();
+    let submit = json!({ "type": "submit", "miner_id": ADDRESS, "nonce": nonce, "job_id": job.job_id, "path": path });
+    submissions.send(submit.to_string()).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while miner_state.accepted_shares.load(Ordering::Relaxed) == 0 {
+        assert!(Instant::now() < deadline, "the block was not accepted in time");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let blocks = node.blocks.lock().await.clone();
+    assert_eq!(blocks.len(), 1);
+    let block = &blocks[0];
+    let header = format!("{}{}{}", job.data, nonce, path);
+    assert!(block.starts_with(&header), "the block does not start with the solved header");
+    assert!(verify_solution(&format!("{}{}", job.data, nonce), &path, &HCGraphUtil::new(None)).is_some());
+
+    // Two transactions: the coinbase paying --address with the witness
+    // commitment, then the template's own transaction.
+    let body = &block[header.len()..];
+    assert!(body.starts_with("02"));
+    assert!(body.contains(&hex::encode(&payout_script)));
+    assert!(body.contains(WITNESS_COMMITMENT));
+    assert!(body.ends_with(TEMPLATE_TX));
+
+    let expected_auth = format!("Basic {}", base64::engine::general_purpose::STANDARD.encode("miner:p@ss"));
+    assert_eq!(node.authorization.lock().await.as_deref(), Some(expected_auth.as_str()));
+}